@@ -9,6 +9,7 @@ fn main() -> wry::Result<()> {
       event_loop::{ControlFlow, EventLoop},
       window::WindowBuilder,
     },
+    http::{Response, StatusCode},
     webview::WebViewBuilder,
   };
 
@@ -20,9 +21,9 @@ fn main() -> wry::Result<()> {
 
   let _webview = WebViewBuilder::new(window)
     .unwrap()
-    .with_custom_protocol("wry.dev".into(), move |_, requested_asset_path| {
+    .with_custom_protocol("wry.dev".into(), move |_, request| {
       // remove the protocol from the path for easiest match
-      let requested_asset_path = requested_asset_path.replace("wry.dev://", "");
+      let requested_asset_path = request.uri().replace("wry.dev://", "");
 
       // sample index.html file
       // files can be bundled easilly into the binary
@@ -64,12 +65,20 @@ fn main() -> wry::Result<()> {
 
       match requested_asset_path.as_str() {
         // if our path match /hello.html
-        "/hello.html" => Ok(hello_html.as_bytes().into()),
+        "/hello.html" => Ok(Response::new(hello_html.as_bytes().into())),
         // if our path match /hello.js
-        "/hello.js" => Ok(hello_js.as_bytes().into()),
-        // other paths should resolve index
+        "/hello.js" => {
+          Ok(Response::new(hello_js.as_bytes().into()).with_mimetype("text/javascript"))
+        }
+        // the root resolves index
+        "" | "/" | "/index.html" => Ok(Response::new(index_html.as_bytes().into())),
+        // other paths are not found
         // more logic can be applied here
-        _ => Ok(index_html.as_bytes().into()),
+        _ => Ok(
+          Response::new(b"Not found".to_vec())
+            .with_status(StatusCode::NOT_FOUND)
+            .with_mimetype("text/plain"),
+        ),
       }
     })
     // tell the webview to load the custom protocol
@@ -3,7 +3,6 @@
 // SPDX-License-Identifier: MIT
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MessageParameters {
@@ -17,7 +16,7 @@ fn main() -> wry::Result<()> {
       event_loop::{ControlFlow, EventLoop},
       window::{Fullscreen, Window, WindowBuilder},
     },
-    webview::{RpcRequest, RpcResponse, WebViewBuilder},
+    webview::{RpcRouter, WebViewBuilder},
   };
 
   let event_loop = EventLoop::new();
@@ -43,43 +42,24 @@ async function getAsyncRpcResult() {
 <div id="rpc-result"></div>
 "#;
 
-  let handler = |window: &Window, mut req: RpcRequest| {
-    let mut response = None;
-    if &req.method == "fullscreen" {
-      if let Some(params) = req.params.take() {
-        if let Ok(mut args) = serde_json::from_value::<Vec<bool>>(params) {
-          if !args.is_empty() {
-            if args.swap_remove(0) {
-              window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-            } else {
-              window.set_fullscreen(None);
-            }
-          };
-          response = Some(RpcResponse::new_result(req.id.take(), None));
-        }
+  let router = RpcRouter::new()
+    .with_method("fullscreen", |window: &Window, (fullscreen,): (bool,)| {
+      if fullscreen {
+        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+      } else {
+        window.set_fullscreen(None);
       }
-    } else if &req.method == "send-parameters" {
-      if let Some(params) = req.params.take() {
-        if let Ok(mut args) = serde_json::from_value::<Vec<MessageParameters>>(params) {
-          let result = if !args.is_empty() {
-            let msg = args.swap_remove(0);
-            Some(Value::String(format!("Hello, {}!", msg.message)))
-          } else {
-            // NOTE: in the real-world we should send an error response here!
-            None
-          };
-          // Must always send a response as this is a `call()`
-          response = Some(RpcResponse::new_result(req.id.take(), result));
-        }
-      }
-    }
+      Ok(())
+    })
+    .with_method(
+      "send-parameters",
+      |_window: &Window, (msg,): (MessageParameters,)| Ok(format!("Hello, {}!", msg.message)),
+    );
 
-    response
-  };
   let webview = WebViewBuilder::new(window)
     .unwrap()
     .with_url(url)?
-    .with_rpc_handler(handler)
+    .with_rpc_router(router)
     .build()?;
 
   event_loop.run(move |event, _, control_flow| {
@@ -0,0 +1,486 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! HTTP-like [`Request`] and [`Response`] types of the custom protocols.
+//!
+//! The handlers registered with [`WebViewBuilder::with_custom_protocol`] receive the method, the
+//! URI, the headers and the body of the request made by the page, like a `fetch` with a custom
+//! scheme, and answer with a status, headers and a body. The body is either in memory or read
+//! while the page consumes it, see [`Body`].
+//!
+//! The method, status and header types are re-exported from the [http] crate.
+//!
+//! [`WebViewBuilder::with_custom_protocol`]: crate::webview::WebViewBuilder::with_custom_protocol
+//! [http]: https://crates.io/crates/http
+
+use std::{
+  fmt,
+  io::{self, Read, Seek, SeekFrom},
+};
+
+pub use ::http::{
+  header::{self, HeaderName, HeaderValue},
+  HeaderMap, Method, StatusCode,
+};
+
+/// A request made by the page to a custom protocol.
+#[derive(Debug, Clone)]
+pub struct Request {
+  method: Method,
+  uri: String,
+  headers: HeaderMap,
+  body: Vec<u8>,
+}
+
+impl Request {
+  /// Create a `GET` request of the URI, without headers or body.
+  pub fn new(uri: &str) -> Self {
+    Self {
+      method: Method::GET,
+      uri: uri.into(),
+      headers: HeaderMap::new(),
+      body: Vec::new(),
+    }
+  }
+
+  /// Set the method of the request.
+  pub fn with_method(mut self, method: Method) -> Self {
+    self.method = method;
+    self
+  }
+
+  /// Add a header to the request. A header which is already set gets another value.
+  pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+    self.headers.append(name, value);
+    self
+  }
+
+  /// Set the body of the request.
+  pub fn with_body(mut self, body: Vec<u8>) -> Self {
+    self.body = body;
+    self
+  }
+
+  /// The method of the request, `GET` unless the page used another one with `fetch`.
+  pub fn method(&self) -> &Method {
+    &self.method
+  }
+
+  /// The full URI of the request, scheme included, like `wry://index.html`.
+  pub fn uri(&self) -> &str {
+    &self.uri
+  }
+
+  /// The headers of the request.
+  ///
+  /// Platform-specific behavior:
+  ///
+  /// - **Linux:** Requires WebKitGTK 2.36 for the headers and 2.40 for the body.
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  /// The body of the request, empty for the requests which don't have any.
+  pub fn body(&self) -> &[u8] {
+    &self.body
+  }
+}
+
+/// The body of a [`Response`].
+pub enum Body {
+  /// A body held in memory.
+  Bytes(Vec<u8>),
+  /// A body read as the page consumes it, with its length in bytes if it's known. Large files or
+  /// generated content can be served this way without loading them in memory first.
+  ///
+  /// Platform-specific behavior:
+  ///
+  /// - **macOS:** The body is read in chunks on another thread, until the page cancels the load.
+  /// - **Windows:** The body is read whole in memory before being given to the webview.
+  Reader(Box<dyn Read + Send>, Option<u64>),
+  /// A body read as the page consumes it from a seekable source, like a file, with its length in
+  /// bytes. The `Range` requests made by the `<video>` and `<audio>` elements to seek are answered
+  /// with the part of the body they ask for.
+  ///
+  /// It's read like [`Body::Reader`].
+  Seekable(Box<dyn ReadSeek>, u64),
+}
+
+/// A [`Read`] and [`Seek`] source of a [`Body::Seekable`].
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+impl Body {
+  /// The length of the body in bytes if it's known.
+  pub fn len(&self) -> Option<u64> {
+    match self {
+      Body::Bytes(bytes) => Some(bytes.len() as u64),
+      Body::Reader(_, length) => *length,
+      Body::Seekable(_, length) => Some(*length),
+    }
+  }
+
+  /// Whether the body is known to be empty.
+  pub fn is_empty(&self) -> bool {
+    self.len() == Some(0)
+  }
+
+  /// The bytes of the body if it's held in memory.
+  pub fn as_bytes(&self) -> Option<&[u8]> {
+    match self {
+      Body::Bytes(bytes) => Some(bytes),
+      Body::Reader(..) | Body::Seekable(..) => None,
+    }
+  }
+
+  /// Read the whole body.
+  #[cfg(target_os = "windows")]
+  pub(crate) fn into_bytes(self) -> io::Result<Vec<u8>> {
+    let length = self.len().unwrap_or(0) as usize;
+    let mut bytes = Vec::with_capacity(length);
+    match self {
+      Body::Bytes(bytes) => return Ok(bytes),
+      Body::Reader(mut reader, _) => reader.read_to_end(&mut bytes)?,
+      Body::Seekable(mut reader, _) => reader.read_to_end(&mut bytes)?,
+    };
+    Ok(bytes)
+  }
+}
+
+impl fmt::Debug for Body {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Body::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+      Body::Reader(_, length) => f.debug_tuple("Reader").field(length).finish(),
+      Body::Seekable(_, length) => f.debug_tuple("Seekable").field(length).finish(),
+    }
+  }
+}
+
+impl From<Vec<u8>> for Body {
+  fn from(bytes: Vec<u8>) -> Self {
+    Body::Bytes(bytes)
+  }
+}
+
+/// The response of a custom protocol handler.
+///
+/// ```
+/// use wry::http::{header, HeaderValue, Response, StatusCode};
+///
+/// let response = Response::new(b"{\"error\":\"not found\"}".to_vec())
+///   .with_status(StatusCode::NOT_FOUND)
+///   .with_mimetype("application/json")
+///   .with_header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+/// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+/// assert_eq!(response.mimetype(), Some("application/json"));
+/// ```
+///
+/// A file can be streamed to the page instead of being read in memory, answering the `Range`
+/// requests of a `<video>` seeking it:
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wry::http::Response;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let response = Response::from_seekable(File::open("video.mp4")?)?.with_mimetype("video/mp4");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Response {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Body,
+}
+
+impl Response {
+  /// Create a `200 OK` response with the body and without headers.
+  ///
+  /// Unless it's set with [`Response::with_mimetype`] or a `Content-Type` header, the content
+  /// type is guessed from the body and the URI of the request.
+  pub fn new(body: Vec<u8>) -> Self {
+    Self {
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      body: Body::Bytes(body),
+    }
+  }
+
+  /// Create a `200 OK` response with a body read as the page consumes it, with its length in
+  /// bytes if it's known, and without headers.
+  ///
+  /// Unless it's set with [`Response::with_mimetype`] or a `Content-Type` header, the content
+  /// type is guessed from the URI of the request.
+  pub fn from_reader<R: Read + Send + 'static>(reader: R, length: Option<u64>) -> Self {
+    Self {
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      body: Body::Reader(Box::new(reader), length),
+    }
+  }
+
+  /// Create a `200 OK` response with a body read as the page consumes it from a seekable source,
+  /// like a file, and without headers. The `Range` requests are answered with a
+  /// `206 Partial Content` response holding the part of the body they ask for.
+  ///
+  /// Unless it's set with [`Response::with_mimetype`] or a `Content-Type` header, the content
+  /// type is guessed from the URI of the request.
+  pub fn from_seekable<R: Read + Seek + Send + 'static>(mut reader: R) -> io::Result<Self> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(Self {
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      body: Body::Seekable(Box::new(reader), length),
+    })
+  }
+
+  /// Set the status of the response.
+  pub fn with_status(mut self, status: StatusCode) -> Self {
+    self.status = status;
+    self
+  }
+
+  /// Add a header to the response. A header which is already set gets another value.
+  pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+    self.headers.append(name, value);
+    self
+  }
+
+  /// Set the content type of the response, like `text/html` or `application/json; charset=utf-8`,
+  /// replacing the `Content-Type` header. A content type which isn't a valid header value is
+  /// ignored.
+  pub fn with_mimetype(mut self, mimetype: &str) -> Self {
+    if let Ok(value) = HeaderValue::from_str(mimetype) {
+      self.headers.insert(header::CONTENT_TYPE, value);
+    }
+    self
+  }
+
+  /// The status of the response.
+  pub fn status(&self) -> StatusCode {
+    self.status
+  }
+
+  /// The headers of the response.
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  /// The body of the response.
+  pub fn body(&self) -> &Body {
+    &self.body
+  }
+
+  /// Take the body of the response.
+  pub fn into_body(self) -> Body {
+    self.body
+  }
+
+  /// The content type of the response if it's set.
+  pub fn mimetype(&self) -> Option<&str> {
+    self
+      .headers
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+  }
+
+  /// The content type of the response, guessed from the body and the URI of the request when
+  /// it's not set.
+  pub(crate) fn content_type(&self, uri: &str) -> String {
+    self.mimetype().map(String::from).unwrap_or_else(|| {
+      crate::webview::MimeType::parse(self.body.as_bytes().unwrap_or_default(), uri)
+    })
+  }
+
+  /// The headers as a raw header string, one `Name: value` line per header, starting with the
+  /// content type.
+  #[cfg(target_os = "windows")]
+  pub(crate) fn raw_headers(&self, uri: &str) -> String {
+    let mut headers = format!("Content-Type: {}", self.content_type(uri));
+    for (name, value) in &self.headers {
+      if name != header::CONTENT_TYPE {
+        if let Ok(value) = value.to_str() {
+          headers.push_str(&format!("\n{}: {}", name, value));
+        }
+      }
+    }
+    headers
+  }
+
+  /// Answer the `Range` request of a single range with the part of the body it asks for, when the
+  /// response is a `200 OK` whose body is in memory or seekable.
+  pub(crate) fn ranged(mut self, request: &Request) -> io::Result<Self> {
+    if self.status != StatusCode::OK {
+      return Ok(self);
+    }
+    let length = match &self.body {
+      Body::Bytes(bytes) => bytes.len() as u64,
+      Body::Seekable(_, length) => *length,
+      Body::Reader(..) => return Ok(self),
+    };
+    self
+      .headers
+      .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let range = request
+      .headers()
+      .get(header::RANGE)
+      .and_then(|range| range.to_str().ok())
+      .and_then(|range| parse_range(range, length));
+    let (start, end) = match range {
+      Some(Some(range)) => range,
+      Some(None) => {
+        self.status = StatusCode::RANGE_NOT_SATISFIABLE;
+        self.body = Body::Bytes(Vec::new());
+        self.insert_content_range(format!("bytes */{}", length));
+        return Ok(self);
+      }
+      None => return Ok(self),
+    };
+
+    self.body = match self.body {
+      Body::Bytes(mut bytes) => {
+        bytes.truncate(end as usize + 1);
+        bytes.drain(..start as usize);
+        Body::Bytes(bytes)
+      }
+      Body::Seekable(mut reader, _) => {
+        reader.seek(SeekFrom::Start(start))?;
+        Body::Reader(
+          Box::new(reader.take(end - start + 1)),
+          Some(end - start + 1),
+        )
+      }
+      body => body,
+    };
+    self.status = StatusCode::PARTIAL_CONTENT;
+    self.insert_content_range(format!("bytes {}-{}/{}", start, end, length));
+    Ok(self)
+  }
+
+  /// Drop the body of the response to a `HEAD` request, keeping its length in the
+  /// `Content-Length` header when it's known.
+  pub(crate) fn into_head(mut self) -> Self {
+    if let Some(length) = self.body.len() {
+      self
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    self.body = Body::Bytes(Vec::new());
+    self
+  }
+
+  fn insert_content_range(&mut self, range: String) {
+    if let Ok(range) = HeaderValue::from_str(&range) {
+      self.headers.insert(header::CONTENT_RANGE, range);
+    }
+  }
+
+  /// The reason phrase of the status, like `Not Found`.
+  pub(crate) fn reason(&self) -> &'static str {
+    self.status.canonical_reason().unwrap_or("Unknown")
+  }
+}
+
+impl From<Vec<u8>> for Response {
+  fn from(body: Vec<u8>) -> Self {
+    Self::new(body)
+  }
+}
+
+// Parse a `Range` header into the first and last byte of the range it asks for in a body of the
+// length. `None` means the header is ignored, like for several ranges, and `Some(None)` that the
+// range can't be satisfied.
+fn parse_range(range: &str, length: u64) -> Option<Option<(u64, u64)>> {
+  let range = range.trim().strip_prefix("bytes=")?;
+  if range.contains(',') {
+    return None;
+  }
+  let dash = range.find('-')?;
+  let (start, end) = (range[..dash].trim(), range[dash + 1..].trim());
+
+  if start.is_empty() {
+    // The last bytes of the body
+    let suffix: u64 = end.parse().ok()?;
+    if suffix == 0 || length == 0 {
+      return Some(None);
+    }
+    return Some(Some((length.saturating_sub(suffix), length - 1)));
+  }
+
+  let start: u64 = start.parse().ok()?;
+  let end = match end {
+    "" => u64::MAX,
+    end => end.parse().ok()?,
+  };
+  if end < start {
+    None
+  } else if start >= length {
+    Some(None)
+  } else {
+    Some(Some((start, end.min(length - 1))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn request(range: &'static str) -> Request {
+    Request::new("wry://video.mp4").with_header(header::RANGE, HeaderValue::from_static(range))
+  }
+
+  #[test]
+  fn should_parse_range() {
+    assert_eq!(parse_range("bytes=0-499", 1000), Some(Some((0, 499))));
+    assert_eq!(parse_range("bytes=500-", 1000), Some(Some((500, 999))));
+    assert_eq!(parse_range("bytes=-300", 1000), Some(Some((700, 999))));
+    assert_eq!(parse_range("bytes=900-2000", 1000), Some(Some((900, 999))));
+    assert_eq!(parse_range("bytes=1000-", 1000), Some(None));
+    assert_eq!(parse_range("bytes=-0", 1000), Some(None));
+    assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+    assert_eq!(parse_range("bytes=9-5", 1000), None);
+    assert_eq!(parse_range("items=0-5", 1000), None);
+  }
+
+  #[test]
+  fn should_answer_range_requests() {
+    let response = Response::new(b"0123456789".to_vec())
+      .ranged(&request("bytes=2-5"))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(response.body().as_bytes(), Some(&b"2345"[..]));
+
+    let response = Response::from_seekable(io::Cursor::new(b"0123456789".to_vec()))
+      .unwrap()
+      .ranged(&request("bytes=-3"))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 7-9/10");
+    assert_eq!(response.body().len(), Some(3));
+    let mut body = Vec::new();
+    if let Body::Reader(mut reader, _) = response.into_body() {
+      reader.read_to_end(&mut body).unwrap();
+    }
+    assert_eq!(body, b"789");
+
+    let response = Response::new(b"0123456789".to_vec())
+      .ranged(&request("bytes=10-"))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+    let response = Response::new(b"0123456789".to_vec())
+      .ranged(&Request::new("wry://video.mp4"))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(response.body().len(), Some(10));
+  }
+}
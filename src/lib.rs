@@ -51,6 +51,12 @@
 //! interacting with the window.
 //! - `protocol`: Enable [`with_custom_protocol`] to define custom URL scheme for handling tasks like
 //! loading assets.
+//! - `msgpack`: Enable [`MessagePackCodec`] to exchange the RPC messages as MessagePack instead of
+//! JSON.
+//! - `embed-gzip` and `embed-brotli`: Decompress the [`EmbeddedAssets`] compressed by `wry-build`
+//! for the pages which don't accept their encoding.
+//! - `derive`: Enable the derive macro of [`TsType`], describing the types of the RPC methods to
+//! TypeScript. This is enabled by default.
 //!
 //! ## Debug build
 //!
@@ -63,6 +69,9 @@
 //! [`WebView`]: crate::webview::WebView
 //! [`with_file_drop_handler`]: crate::webview::WebView::with_file_drop_handler
 //! [`with_custom_protocol`]: crate::webview::WebView::with_custom_protocol
+//! [`MessagePackCodec`]: crate::webview::MessagePackCodec
+//! [`TsType`]: crate::webview::TsType
+//! [`EmbeddedAssets`]: crate::webview::EmbeddedAssets
 
 #![allow(clippy::new_without_default)]
 #![allow(clippy::wrong_self_convention)]
@@ -81,6 +90,9 @@ extern crate thiserror;
 #[cfg(target_os = "macos")]
 #[macro_use]
 extern crate objc;
+// The code generated by the derive macros refers to `::wry`, including in the tests of this crate.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as wry;
 
 use std::sync::mpsc::{RecvError, SendError};
 
@@ -90,6 +102,7 @@ use url::ParseError;
 use winit::window::BadIcon;
 
 pub mod application;
+pub mod http;
 pub mod webview;
 
 /// Convenient type alias of Result type for wry.
@@ -112,6 +125,16 @@ pub enum Error {
   #[error("Bad RPC request: {0} ((1))")]
   RpcScriptError(String, String),
   #[error(transparent)]
+  RpcError(#[from] crate::webview::RpcError),
+  #[error("{0}")]
+  HandlerPanic(crate::webview::HandlerPanic),
+  #[error("Failed to evaluate the script: {0}")]
+  EvalError(String),
+  #[error("Failed to load {0}: {1}")]
+  NavigationError(String, String),
+  #[error("The custom protocol name {0} is reserved")]
+  ReservedProtocol(String),
+  #[error(transparent)]
   NulError(#[from] std::ffi::NulError),
   #[error(transparent)]
   OsError(#[from] crate::application::error::OsError),
@@ -123,6 +146,12 @@ pub enum Error {
   MessageSender,
   #[error(transparent)]
   Json(#[from] serde_json::Error),
+  #[cfg(feature = "msgpack")]
+  #[error(transparent)]
+  MsgPackEncode(#[from] rmp_serde::encode::Error),
+  #[cfg(feature = "msgpack")]
+  #[error(transparent)]
+  MsgPackDecode(#[from] rmp_serde::decode::Error),
   #[error(transparent)]
   UrlError(#[from] ParseError),
   #[error("IO error: {0}")]
@@ -0,0 +1,279 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+  fs::File,
+  io,
+  path::{Path, PathBuf},
+};
+
+use super::mimetype::MimeType;
+use crate::{
+  application::window::Window,
+  http::{header, HeaderValue, Method, Request, Response, StatusCode},
+  Result,
+};
+
+/// A custom protocol serving the files of a directory, like the `dist` folder of a web bundle.
+///
+/// The path of the request URI, without its scheme, query and fragment, is resolved in the
+/// directory. The paths escaping it with `..` are not found, the directories are answered with
+/// their `index.html` and the content type is guessed from the extension of the file. The files
+/// are streamed and answer the `Range` requests, and the `HEAD` requests get their headers only.
+///
+/// ```no_run
+/// # fn main() -> wry::Result<()> {
+/// use wry::{
+///   application::{event_loop::EventLoop, window::WindowBuilder},
+///   webview::{AssetProtocol, WebViewBuilder},
+/// };
+///
+/// let event_loop = EventLoop::new();
+/// let window = WindowBuilder::new().build(&event_loop)?;
+/// let assets = AssetProtocol::from_dir("dist").with_spa_fallback(true);
+/// let _webview = WebViewBuilder::new(window)?
+///   .with_custom_protocol("app".into(), assets.into_handler())
+///   .with_url("app://index.html")?
+///   .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AssetProtocol {
+  root: PathBuf,
+  spa_fallback: bool,
+}
+
+impl AssetProtocol {
+  /// Create a protocol serving the files of the directory.
+  pub fn from_dir<P: Into<PathBuf>>(path: P) -> Self {
+    Self {
+      root: path.into(),
+      spa_fallback: false,
+    }
+  }
+
+  /// Answer the paths which aren't found with the `index.html` of the directory, for the single
+  /// page applications routing on the client side. Only the paths without an extension, like
+  /// `/users/42`, fall back: a missing `/main.js` is still not found.
+  pub fn with_spa_fallback(mut self, spa_fallback: bool) -> Self {
+    self.spa_fallback = spa_fallback;
+    self
+  }
+
+  /// Answer a request of the page. Only `GET` and `HEAD` requests are allowed.
+  pub fn respond(&self, request: &Request) -> Result<Response> {
+    respond(self, request)
+  }
+
+  /// Get the handler to give to [`WebViewBuilder::with_custom_protocol`].
+  ///
+  /// [`WebViewBuilder::with_custom_protocol`]: super::WebViewBuilder::with_custom_protocol
+  pub fn into_handler(self) -> impl Fn(&Window, &Request) -> Result<Response> + 'static {
+    handler(self)
+  }
+}
+
+impl Assets for AssetProtocol {
+  fn spa_fallback(&self) -> bool {
+    self.spa_fallback
+  }
+
+  fn serve(&self, path: &Path, _: &Request) -> io::Result<Response> {
+    let path = self.root.join(path);
+    let path = if path.is_dir() {
+      path.join("index.html")
+    } else {
+      path
+    };
+    let mimetype = MimeType::parse_from_uri(&path.to_string_lossy()).to_string();
+    Ok(Response::from_seekable(File::open(&path)?)?.with_mimetype(&mimetype))
+  }
+}
+
+// The files served by a custom protocol, from a directory or embedded in the binary.
+pub(super) trait Assets {
+  // Whether the routes of a single page application are answered with the `index.html`.
+  fn spa_fallback(&self) -> bool;
+
+  // Answer with the file of a path relative to the root, or the `index.html` of a directory.
+  fn serve(&self, path: &Path, request: &Request) -> io::Result<Response>;
+}
+
+// Answer a request of the page with the assets, allowing `GET` and `HEAD` requests only. The paths
+// without an extension which aren't found fall back to the `index.html` if the assets want it.
+pub(super) fn respond<A: Assets>(assets: &A, request: &Request) -> Result<Response> {
+  match *request.method() {
+    Method::GET | Method::HEAD => {}
+    _ => {
+      return Ok(
+        status_response(StatusCode::METHOD_NOT_ALLOWED)
+          .with_header(header::ALLOW, HeaderValue::from_static("GET, HEAD")),
+      )
+    }
+  }
+
+  let path = match asset_path(request.uri()) {
+    Some(path) => path,
+    None => return Ok(status_response(StatusCode::NOT_FOUND)),
+  };
+  let response = match assets.serve(&path, request) {
+    Err(e)
+      if e.kind() == io::ErrorKind::NotFound
+        && assets.spa_fallback()
+        && path.extension().is_none() =>
+    {
+      assets.serve(Path::new("index.html"), request)
+    }
+    result => result,
+  }
+  .or_else(|e| match e.kind() {
+    io::ErrorKind::NotFound => Ok(status_response(StatusCode::NOT_FOUND)),
+    io::ErrorKind::PermissionDenied => Ok(status_response(StatusCode::FORBIDDEN)),
+    _ => Err(e),
+  })?;
+  // The headers of a `HEAD` request are the ones of the part a `GET` would get
+  Ok(match *request.method() {
+    Method::HEAD => response.ranged(request)?.into_head(),
+    _ => response,
+  })
+}
+
+// Get the handler answering the requests with the assets.
+pub(super) fn handler<A: Assets + 'static>(
+  assets: A,
+) -> impl Fn(&Window, &Request) -> Result<Response> + 'static {
+  move |_: &Window, request: &Request| respond(&assets, request)
+}
+
+fn status_response(status: StatusCode) -> Response {
+  Response::new(status.canonical_reason().unwrap_or_default().into())
+    .with_status(status)
+    .with_mimetype("text/plain")
+}
+
+// Get the path of the asset of a request URI, relative to the directory, without the scheme, query
+// and fragment of the URI. `None` means the path would escape the directory.
+fn asset_path(uri: &str) -> Option<PathBuf> {
+  let path = uri.splitn(2, "://").last().unwrap_or_default();
+  let path = path.split(&['?', '#'][..]).next().unwrap_or_default();
+  let mut asset = PathBuf::new();
+  for segment in path.split('/') {
+    let segment = percent_decode(segment)?;
+    match segment.as_str() {
+      "" | "." => {}
+      ".." => return None,
+      // Separators or drive prefixes in a segment could lead anywhere
+      segment if segment.contains(&['/', '\\', ':', '\0'][..]) => return None,
+      segment => asset.push(segment),
+    }
+  }
+  Some(asset)
+}
+
+// Decode the `%XX` escapes of a URI segment, or `None` if it's not UTF-8 once decoded.
+fn percent_decode(segment: &str) -> Option<String> {
+  let bytes = segment.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = match bytes[i] {
+      b'%' => segment
+        .get(i + 1..i + 3)
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+      _ => None,
+    };
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_resolve_asset_paths() {
+    assert_eq!(asset_path("app://index.html"), Some("index.html".into()));
+    assert_eq!(
+      asset_path("app:///assets/main.js?v=2#top"),
+      Some(["assets", "main.js"].iter().collect())
+    );
+    assert_eq!(
+      asset_path("app://fonts/Open%20Sans.woff2"),
+      Some(["fonts", "Open Sans.woff2"].iter().collect())
+    );
+    assert_eq!(asset_path("app://./index.html"), Some("index.html".into()));
+    assert_eq!(asset_path("app://"), Some(PathBuf::new()));
+    assert_eq!(asset_path("app://../secret"), None);
+    assert_eq!(asset_path("app://assets/%2E%2E/%2E%2E/secret"), None);
+    assert_eq!(asset_path("app://assets/%2F..%2Fsecret"), None);
+    assert_eq!(asset_path("app://C:%5Csecret"), None);
+  }
+
+  #[test]
+  fn should_serve_directory() {
+    let root = std::env::temp_dir().join(format!("wry-asset-protocol-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("index.html"), "<h1>index</h1>").unwrap();
+    std::fs::write(root.join("assets").join("main.js"), "main()").unwrap();
+
+    let assets = AssetProtocol::from_dir(&root);
+    let response = assets.respond(&Request::new("app://")).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.mimetype(), Some("text/html; charset=utf-8"));
+    assert_eq!(response.body().len(), Some(14));
+
+    let response = assets
+      .respond(&Request::new("app://assets/main.js"))
+      .unwrap();
+    assert_eq!(response.mimetype(), Some("text/javascript; charset=utf-8"));
+
+    let response = assets.respond(&Request::new("app://about")).unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = assets
+      .respond(&Request::new("app://index.html").with_method(Method::POST))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let response = assets
+      .respond(&Request::new("app://index.html").with_method(Method::HEAD))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "14");
+    assert_eq!(response.body().as_bytes(), Some(&[][..]));
+    let response = assets
+      .respond(
+        &Request::new("app://index.html")
+          .with_method(Method::HEAD)
+          .with_header(header::RANGE, HeaderValue::from_static("bytes=4-")),
+      )
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 4-13/14");
+    assert_eq!(response.body().as_bytes(), Some(&[][..]));
+
+    let assets = assets.with_spa_fallback(true);
+    let response = assets.respond(&Request::new("app://users/42")).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.mimetype(), Some("text/html; charset=utf-8"));
+    let response = assets
+      .respond(&Request::new("app://assets/missing.js"))
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
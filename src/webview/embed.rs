@@ -0,0 +1,252 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{io, path::Path};
+
+use super::{
+  asset::{self, Assets},
+  mimetype::MimeType,
+};
+use crate::{
+  application::window::Window,
+  http::{header, HeaderValue, Request, Response},
+  Result,
+};
+
+/// The files of a directory embedded in the binary by a build script with the
+/// `EmbeddedAssetsBuilder` of the `wry-build` crate, served by a custom protocol like
+/// [`AssetProtocol`] does, so the application doesn't depend on files next to it.
+///
+/// In `build.rs`, with `wry-build` in the `[build-dependencies]`:
+///
+/// ```ignore
+/// let out_dir = std::env::var("OUT_DIR").unwrap();
+/// wry_build::EmbeddedAssetsBuilder::new("dist").write(format!("{}/assets.rs", out_dir))?;
+/// ```
+///
+/// And in the application:
+///
+/// ```ignore
+/// static ASSETS: EmbeddedAssets = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+///
+/// let _webview = WebViewBuilder::new(window)?
+///   .with_custom_protocol("app".into(), ASSETS.into_handler())
+///   .with_url("app://index.html")?
+///   .build()?;
+/// ```
+///
+/// [`AssetProtocol`]: super::AssetProtocol
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedAssets {
+  assets: &'static [EmbeddedAsset],
+  spa_fallback: bool,
+}
+
+/// A file embedded in the binary.
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedAsset {
+  /// The path of the file in the directory, with `/` separators.
+  pub path: &'static str,
+  /// The content of the file, compressed if it has an encoding.
+  pub bytes: &'static [u8],
+  /// The `Content-Encoding` of the content, `gzip` or `br`.
+  pub encoding: Option<&'static str>,
+}
+
+impl EmbeddedAsset {
+  /// The encoding of the files compressed with gzip by `wry-build`. It only exists with the
+  /// `embed-gzip` feature, which decompresses them, so the assets don't build without it.
+  #[cfg(feature = "embed-gzip")]
+  pub const GZIP: &'static str = "gzip";
+  /// The encoding of the files compressed with brotli by `wry-build`. It only exists with the
+  /// `embed-brotli` feature, which decompresses them, so the assets don't build without it.
+  #[cfg(feature = "embed-brotli")]
+  pub const BROTLI: &'static str = "br";
+}
+
+impl EmbeddedAssets {
+  /// Create the embedded assets from files sorted by path, like `EmbeddedAssetsBuilder` writes
+  /// them.
+  pub const fn new(assets: &'static [EmbeddedAsset]) -> Self {
+    Self {
+      assets,
+      spa_fallback: false,
+    }
+  }
+
+  /// Answer the paths without an extension which aren't found with the `index.html`, like
+  /// [`AssetProtocol::with_spa_fallback`].
+  ///
+  /// [`AssetProtocol::with_spa_fallback`]: super::AssetProtocol::with_spa_fallback
+  pub fn with_spa_fallback(mut self, spa_fallback: bool) -> Self {
+    self.spa_fallback = spa_fallback;
+    self
+  }
+
+  /// Get the file of the path.
+  pub fn get(&self, path: &str) -> Option<&'static EmbeddedAsset> {
+    let assets = self.assets;
+    assets
+      .binary_search_by(|asset| asset.path.cmp(path))
+      .ok()
+      .map(|i| &assets[i])
+  }
+
+  /// Answer a request of the page. Only `GET` and `HEAD` requests are allowed.
+  ///
+  /// A compressed file is sent as it is to the pages accepting its encoding with the
+  /// `Accept-Encoding` header, and decompressed for the others.
+  pub fn respond(&self, request: &Request) -> Result<Response> {
+    asset::respond(self, request)
+  }
+
+  /// Get the handler to give to [`WebViewBuilder::with_custom_protocol`].
+  ///
+  /// [`WebViewBuilder::with_custom_protocol`]: super::WebViewBuilder::with_custom_protocol
+  pub fn into_handler(self) -> impl Fn(&Window, &Request) -> Result<Response> + 'static {
+    asset::handler(self)
+  }
+}
+
+impl Assets for EmbeddedAssets {
+  fn spa_fallback(&self) -> bool {
+    self.spa_fallback
+  }
+
+  fn serve(&self, path: &Path, request: &Request) -> io::Result<Response> {
+    let path = path.iter().filter_map(|s| s.to_str()).collect::<Vec<_>>();
+    let index = path
+      .iter()
+      .chain(&["index.html"])
+      .copied()
+      .collect::<Vec<_>>();
+    let asset = self
+      .get(&path.join("/"))
+      .or_else(|| self.get(&index.join("/")))
+      .ok_or(io::ErrorKind::NotFound)?;
+
+    let response = match asset.encoding {
+      Some(encoding) if accepts(request, encoding) => Response::new(asset.bytes.to_vec())
+        .with_header(header::CONTENT_ENCODING, HeaderValue::from_static(encoding))
+        .with_header(header::VARY, HeaderValue::from_static("Accept-Encoding")),
+      Some(_) => Response::new(decode(asset)?)
+        .with_header(header::VARY, HeaderValue::from_static("Accept-Encoding")),
+      None => Response::new(asset.bytes.to_vec()),
+    };
+    Ok(response.with_mimetype(&MimeType::parse_from_uri(asset.path).to_string()))
+  }
+}
+
+// Whether the `Accept-Encoding` header of the request accepts the encoding.
+fn accepts(request: &Request, encoding: &str) -> bool {
+  request
+    .headers()
+    .get_all(header::ACCEPT_ENCODING)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|coding| {
+      let mut params = coding.split(';').map(str::trim);
+      params.next() == Some(encoding)
+        && params.all(|param| match param.strip_prefix("q=") {
+          Some(q) => q.parse::<f32>().map(|q| q > 0.0).unwrap_or(true),
+          None => true,
+        })
+    })
+}
+
+// Decompress the content of a compressed file.
+fn decode(asset: &EmbeddedAsset) -> io::Result<Vec<u8>> {
+  match asset.encoding {
+    #[cfg(feature = "embed-gzip")]
+    Some(EmbeddedAsset::GZIP) => {
+      use std::io::Read;
+      let mut decoded = Vec::new();
+      flate2::read::GzDecoder::new(asset.bytes).read_to_end(&mut decoded)?;
+      Ok(decoded)
+    }
+    #[cfg(feature = "embed-brotli")]
+    Some(EmbeddedAsset::BROTLI) => {
+      use std::io::Read;
+      let mut decoded = Vec::new();
+      brotli::Decompressor::new(asset.bytes, 4096).read_to_end(&mut decoded)?;
+      Ok(decoded)
+    }
+    encoding => Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("Can't decode {} from {:?}", asset.path, encoding),
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{Method, StatusCode};
+
+  static ASSETS: EmbeddedAssets = EmbeddedAssets::new(&[
+    EmbeddedAsset {
+      path: "assets/main.js",
+      bytes: b"main()",
+      encoding: None,
+    },
+    EmbeddedAsset {
+      path: "index.html",
+      bytes: b"<h1>index</h1>",
+      encoding: None,
+    },
+  ]);
+
+  #[test]
+  fn should_serve_embedded_assets() {
+    let response = ASSETS.respond(&Request::new("app://")).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.mimetype(), Some("text/html; charset=utf-8"));
+    assert_eq!(response.body().as_bytes(), Some(&b"<h1>index</h1>"[..]));
+
+    let response = ASSETS
+      .respond(&Request::new("app:///assets/main.js"))
+      .unwrap();
+    assert_eq!(response.mimetype(), Some("text/javascript; charset=utf-8"));
+
+    let response = ASSETS.respond(&Request::new("app://about")).unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = ASSETS
+      .respond(&Request::new("app://index.html").with_method(Method::HEAD))
+      .unwrap();
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "14");
+    assert_eq!(response.body().as_bytes(), Some(&[][..]));
+
+    let assets = ASSETS.with_spa_fallback(true);
+    let response = assets.respond(&Request::new("app://about")).unwrap();
+    assert_eq!(response.body().as_bytes(), Some(&b"<h1>index</h1>"[..]));
+    let response = assets.respond(&Request::new("app://main.css")).unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[cfg(feature = "embed-gzip")]
+  #[test]
+  fn should_decode_compressed_assets() {
+    use std::io::Write;
+
+    let html = "<p>wry</p>".repeat(100);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(html.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let assets = EmbeddedAssets::new(Box::leak(Box::new([EmbeddedAsset {
+      path: "index.html",
+      bytes: Box::leak(compressed.into_boxed_slice()),
+      encoding: Some("gzip"),
+    }])));
+    let request = Request::new("app://index.html").with_header(
+      header::ACCEPT_ENCODING,
+      HeaderValue::from_static("gzip, deflate"),
+    );
+    let response = assets.respond(&request).unwrap();
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    let response = assets.respond(&Request::new("app://index.html")).unwrap();
+    assert_eq!(response.body().as_bytes(), Some(html.as_bytes()));
+  }
+}
@@ -0,0 +1,255 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+// The request interceptor of WebKitGTK.
+//
+// The requests are sent by the web process, where the `wry-webextension` library blocks and
+// rewrites them in the `send-request` signal of the pages. It asks the webview about each request
+// through a socket, with a line of JSON per request and per decision, and the interceptor runs on
+// the main thread as the socket is read. The responses given by the interceptor are served with
+// the `wry-intercept` protocol, which the requests are redirected to.
+//
+// Without the library, the interceptor observes the requests from the `resource-load-started`
+// signal, which comes too late to change them: only the page loads can be blocked or redirected.
+
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  fs,
+  io::{self, Read, Write},
+  os::unix::{
+    fs::DirBuilderExt,
+    io::AsRawFd,
+    net::{UnixListener, UnixStream},
+  },
+  path::PathBuf,
+  rc::Rc,
+};
+
+use glib::{IOCondition, ToVariant};
+use serde_json::{json, Value};
+use webkit2gtk::{
+  SecurityManagerExt, URIRequestExt, URISchemeRequestExt, WebContext, WebContextExt, WebView,
+  WebViewExt,
+};
+
+use super::protocol;
+use crate::{
+  application::window::Window,
+  http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode},
+  webview::{rpc, Interception, INTERCEPT_PROTOCOL},
+  Error, Result,
+};
+
+/// The file name of the `wry-webextension` library, looked up next to the executable.
+const EXTENSION_FILE: &str = "libwry_webextension.so";
+
+type Interceptor = Box<dyn Fn(&Window, &Request) -> Interception>;
+
+// The responses of the interceptor waiting to be loaded, with the URI of their request
+type Responses = Rc<RefCell<HashMap<String, (String, Response)>>>;
+
+/// The socket the web process connects to, removed with the webview.
+pub(super) struct Server {
+  directory: PathBuf,
+  source: Option<glib::SourceId>,
+}
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    if let Some(source) = self.source.take() {
+      glib::source_remove(source);
+    }
+    let _ = fs::remove_dir_all(&self.directory);
+  }
+}
+
+/// Set the interceptor of the requests of a webview, before it loads anything.
+pub(super) fn connect(
+  context: &WebContext,
+  webview: &WebView,
+  window: Rc<Window>,
+  interceptor: Interceptor,
+) -> Result<Option<Server>> {
+  let extension = std::env::current_exe()
+    .ok()
+    .and_then(|exe| Some(exe.parent()?.join(EXTENSION_FILE)))
+    .filter(|extension| extension.exists());
+  match extension {
+    Some(extension) => serve(context, extension, window, interceptor).map(Some),
+    None => {
+      log::warn!(
+        "{} isn't next to the executable, the request interceptor can only block and redirect the page loads",
+        EXTENSION_FILE
+      );
+      observe(webview, window, interceptor);
+      Ok(None)
+    }
+  }
+}
+
+// Load the extension in the web process and answer it through a socket.
+fn serve(
+  context: &WebContext,
+  extension: PathBuf,
+  window: Rc<Window>,
+  interceptor: Interceptor,
+) -> Result<Server> {
+  // WebKitGTK loads every library of the directory, so it only holds the extension, and the
+  // socket is only open to the user
+  let directory = std::env::temp_dir().join(format!("wry-{}", rpc::new_token()));
+  fs::DirBuilder::new().mode(0o700).create(&directory)?;
+  let mut server = Server {
+    directory,
+    source: None,
+  };
+  std::os::unix::fs::symlink(&extension, server.directory.join(EXTENSION_FILE))?;
+  let socket = server.directory.join("socket");
+  let listener = UnixListener::bind(&socket)?;
+  listener.set_nonblocking(true)?;
+  context.set_web_extensions_directory(&server.directory.to_string_lossy());
+  context.set_web_extensions_initialization_user_data(&socket.to_string_lossy().to_variant());
+
+  // The responses are loaded by pages of any origin
+  let responses = Responses::default();
+  let security_manager = context
+    .get_security_manager()
+    .ok_or(Error::MissingManager)?;
+  security_manager.register_uri_scheme_as_secure(INTERCEPT_PROTOCOL);
+  security_manager.register_uri_scheme_as_cors_enabled(INTERCEPT_PROTOCOL);
+  let r = responses.clone();
+  context.register_uri_scheme(INTERCEPT_PROTOCOL, move |request| {
+    let uri = request
+      .get_uri()
+      .map(|uri| uri.to_string())
+      .unwrap_or_default();
+    let token = uri.rsplit('/').next().unwrap_or_default();
+    match r.borrow_mut().remove(token) {
+      Some((uri, response)) => protocol::finish(request, &uri, response),
+      None => protocol::finish(
+        request,
+        &uri,
+        Response::new(Vec::new()).with_status(StatusCode::NOT_FOUND),
+      ),
+    }
+  });
+
+  let decide: Rc<dyn Fn(&str) -> Value> =
+    Rc::new(move |message| decide(&window, &interceptor, &responses, message));
+  server.source = Some(glib::unix_fd_add_local(
+    listener.as_raw_fd(),
+    IOCondition::IN,
+    move |_, _| {
+      while let Ok((stream, _)) = listener.accept() {
+        if stream.set_nonblocking(true).is_ok() {
+          answer(stream, decide.clone());
+        }
+      }
+      glib::Continue(true)
+    },
+  ));
+  Ok(server)
+}
+
+// Answer the requests of a web process as they're read, until it closes the connection.
+fn answer(mut stream: UnixStream, decide: Rc<dyn Fn(&str) -> Value>) {
+  let mut buffer = Vec::new();
+  glib::unix_fd_add_local(
+    stream.as_raw_fd(),
+    IOCondition::IN | IOCondition::HUP | IOCondition::ERR,
+    move |_, _| {
+      let mut chunk = [0; 4096];
+      loop {
+        match stream.read(&mut chunk) {
+          Ok(0) => return glib::Continue(false),
+          Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+          Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+          Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+          Err(_) => return glib::Continue(false),
+        }
+      }
+      while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let mut reply = decide(&String::from_utf8_lossy(&line[..end])).to_string();
+        reply.push('\n');
+        if stream.write_all(reply.as_bytes()).is_err() {
+          return glib::Continue(false);
+        }
+      }
+      glib::Continue(true)
+    },
+  );
+}
+
+// Ask the interceptor about a request of the web process. The responses are kept until the page
+// loads them from the protocol.
+fn decide(
+  window: &Window,
+  interceptor: &Interceptor,
+  responses: &Responses,
+  message: &str,
+) -> Value {
+  let request = match parse_request(message) {
+    Some(request) => request,
+    None => return json!({ "action": "block" }),
+  };
+  match interceptor(window, &request) {
+    Interception::Allow => json!({ "action": "allow" }),
+    Interception::Block => json!({ "action": "block" }),
+    Interception::Redirect(uri) => json!({ "action": "redirect", "uri": uri }),
+    Interception::Respond(response) => {
+      let token = rpc::new_token();
+      let uri = format!("{}://localhost/{}", INTERCEPT_PROTOCOL, token);
+      responses
+        .borrow_mut()
+        .insert(token, (request.uri().into(), response));
+      json!({ "action": "redirect", "uri": uri })
+    }
+  }
+}
+
+// Get the request sent by the web process, with its URI, method and headers.
+fn parse_request(message: &str) -> Option<Request> {
+  let message: Value = serde_json::from_str(message).ok()?;
+  let mut request = Request::new(message["uri"].as_str()?);
+  if let Some(Ok(method)) = message["method"]
+    .as_str()
+    .map(|method| Method::from_bytes(method.as_bytes()))
+  {
+    request = request.with_method(method);
+  }
+  for header in message["headers"].as_array().into_iter().flatten() {
+    if let (Some(name), Some(value)) = (header[0].as_str(), header[1].as_str()) {
+      if let (Ok(name), Ok(value)) = (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(value),
+      ) {
+        request = request.with_header(name, value);
+      }
+    }
+  }
+  Some(request)
+}
+
+// Show the requests to the interceptor once they're sent, blocking or redirecting the page loads.
+fn observe(webview: &WebView, window: Rc<Window>, interceptor: Interceptor) {
+  webview.connect_resource_load_started(move |webview, resource, request| {
+    let uri = match request.get_uri() {
+      Some(uri) if uri.starts_with("http://") || uri.starts_with("https://") => uri.to_string(),
+      _ => return,
+    };
+    let page = webview.get_main_resource().as_ref() == Some(resource);
+    match interceptor(&window, &Request::new(&uri)) {
+      Interception::Allow => {}
+      Interception::Block if page => webview.stop_loading(),
+      Interception::Redirect(uri) if page => webview.load_uri(&uri),
+      interception => log::warn!(
+        "Ignoring {:?} for {}, which needs {}",
+        interception,
+        uri,
+        EXTENSION_FILE
+      ),
+    }
+  });
+}
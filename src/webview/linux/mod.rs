@@ -2,15 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::{path::PathBuf, rc::Rc};
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
 
 use gdk::{WindowEdge, WindowExt, RGBA};
 use gio::Cancellable;
-use glib::{signal::Inhibit, Bytes, FileError};
+use glib::{signal::Inhibit, FileError};
 use gtk::{ContainerExt, WidgetExt};
 use url::Url;
 use webkit2gtk::{
-  SecurityManagerExt, SettingsExt, URISchemeRequestExt, UserContentInjectedFrames,
+  LoadEvent, SecurityManagerExt, SettingsExt, URISchemeRequestExt, UserContentInjectedFrames,
   UserContentManager, UserContentManagerExt, UserScript, UserScriptInjectionTime,
   WebContextBuilder, WebContextExt, WebView, WebViewExt, WebViewExtManual,
   WebsiteDataManagerBuilder,
@@ -18,14 +18,22 @@ use webkit2gtk::{
 
 use crate::{
   application::window::Window,
-  webview::{mimetype::MimeType, FileDropEvent, RpcRequest, RpcResponse},
+  http::{Request, Response},
+  webview::{
+    rpc::{self, RpcHandler},
+    ErrorHandler, FileDropEvent, Interception, ProtocolOptions,
+  },
   Error, Result,
 };
 
 mod file_drop;
+mod intercept;
+mod protocol;
 
 pub struct InnerWebView {
   webview: Rc<WebView>,
+  eval_errors: glib::Sender<String>,
+  _intercept_server: Option<intercept::Server>,
 }
 
 impl InnerWebView {
@@ -36,11 +44,14 @@ impl InnerWebView {
     transparent: bool,
     custom_protocols: Vec<(
       String,
-      Box<dyn Fn(&Window, &str) -> Result<Vec<u8>> + 'static>,
+      ProtocolOptions,
+      Box<dyn Fn(&Window, &Request) -> Response + 'static>,
     )>,
-    rpc_handler: Option<Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>>,
+    request_interceptor: Option<Box<dyn Fn(&Window, &Request) -> Interception>>,
+    rpc_handler: Option<RpcHandler>,
     file_drop_handler: Option<Box<dyn Fn(&Window, FileDropEvent) -> bool>>,
     data_directory: Option<PathBuf>,
+    error_handler: Option<ErrorHandler>,
   ) -> Result<Self> {
     let window_rc = Rc::clone(&window);
     let window = &window.window;
@@ -70,25 +81,81 @@ impl InnerWebView {
     let webview = Rc::new(WebView::new_with_context_and_user_content_manager(
       &context, &manager,
     ));
+    // The interceptor loads the web extension, which must be set before anything is loaded
+    let intercept_server = match request_interceptor {
+      Some(interceptor) => intercept::connect(&context, &webview, window_rc.clone(), interceptor)?,
+      None => None,
+    };
+
+    // The messages don't tell which frame sent them, so every document gets a new key with the
+    // script sending the messages, bound to its URI once the document is committed. The messages
+    // of the other frames, which don't get the script, and of the previous documents are rejected.
+    let keys = Rc::new(RefCell::new(DocumentKeys {
+      next: rpc::new_token(),
+      current: None,
+    }));
+    add_scripts(&manager, &keys.borrow().next, &scripts);
+    let m = manager.clone();
+    let k = keys.clone();
+    let dispatcher = rpc_handler.as_ref().map(|rpc_handler| {
+      (
+        rpc_handler.dispatcher.clone(),
+        rpc_handler.panic_hook.clone(),
+      )
+    });
+    webview.connect_load_changed(move |webview, event| match event {
+      LoadEvent::Started => {
+        let mut keys = k.borrow_mut();
+        keys.next = rpc::new_token();
+        m.remove_all_scripts();
+        add_scripts(&m, &keys.next, &scripts);
+      }
+      LoadEvent::Committed => {
+        let mut keys = k.borrow_mut();
+        let key = keys.next.clone();
+        keys.current = webview.get_uri().map(|uri| (key, uri.to_string()));
+        // The `call_js` calls and the RPC bytes of a page are gone with it
+        if let Some((dispatcher, panic_hook)) = &dispatcher {
+          dispatcher.navigated(panic_hook.as_ref());
+        }
+      }
+      _ => {}
+    });
+
+    // The evaluations complete in a callback which must be `Send`, so their errors are passed to
+    // the error handler through a channel of the main context
+    let (eval_errors, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    let w = window_rc.clone();
+    let handler = error_handler.clone();
+    receiver.attach(None, move |error| {
+      super::report_error(handler.as_ref(), &w, Error::EvalError(error));
+      glib::Continue(true)
+    });
 
     // Message handler
     let wv = Rc::clone(&webview);
     let w = window_rc.clone();
+    let handler = error_handler.clone();
+    let errors = eval_errors.clone();
     manager.register_script_message_handler("external");
     manager.connect_script_message_received(move |_m, msg| {
       if let (Some(js), Some(context)) = (msg.get_value(), msg.get_global_context()) {
         if let Some(js) = js.to_string(&context) {
           if let Some(rpc_handler) = rpc_handler.as_ref() {
-            match super::rpc_proxy(&w, js, rpc_handler) {
+            let (source, js) = match keys.borrow().source(&js) {
+              Some((source, js)) => (source.to_string(), js.to_string()),
+              None => {
+                log::warn!("Dropping an RPC message from an unknown frame or document");
+                return;
+              }
+            };
+            match super::rpc_proxy(&w, js, Some(&source), rpc_handler) {
               Ok(result) => {
                 if let Some(ref script) = result {
-                  let cancellable: Option<&Cancellable> = None;
-                  wv.run_javascript(script, cancellable, |_| ());
+                  run_javascript(&wv, script, errors.clone());
                 }
               }
-              Err(e) => {
-                eprintln!("{}", e);
-              }
+              Err(e) => super::report_error(handler.as_ref(), &w, e),
             }
           }
         }
@@ -110,6 +177,16 @@ impl InnerWebView {
       Inhibit(false)
     });
 
+    // Navigation errors
+    let w = window_rc.clone();
+    let handler = error_handler.clone();
+    webview.connect_load_failed(move |_, _, uri, error| {
+      let error = Error::NavigationError(uri.into(), error.to_string());
+      super::report_error(handler.as_ref(), &w, error);
+      // Let the web engine show its error page
+      false
+    });
+
     window.add(&*webview);
     webview.grab_focus();
 
@@ -154,36 +231,38 @@ impl InnerWebView {
       window.show_all();
     }
 
-    let w = Self { webview };
-
-    // Initialize scripts
-    w.init("window.external={invoke:function(x){window.webkit.messageHandlers.external.postMessage(x);}}")?;
-    for js in scripts {
-      w.init(&js)?;
-    }
+    let w = Self {
+      webview,
+      eval_errors,
+      _intercept_server: intercept_server,
+    };
 
     // Custom protocol
-    for (name, handler) in custom_protocols {
-      context
+    for (name, options, handler) in custom_protocols {
+      let security_manager = context
         .get_security_manager()
-        .ok_or(Error::MissingManager)?
-        .register_uri_scheme_as_secure(&name);
+        .ok_or(Error::MissingManager)?;
+      if options.secure {
+        security_manager.register_uri_scheme_as_secure(&name);
+      }
+      if options.cors_enabled {
+        security_manager.register_uri_scheme_as_cors_enabled(&name);
+      }
+      if options.local {
+        security_manager.register_uri_scheme_as_local(&name);
+      }
+      if options.display_isolated {
+        security_manager.register_uri_scheme_as_display_isolated(&name);
+      }
+      if options.empty_document {
+        security_manager.register_uri_scheme_as_empty_document(&name);
+      }
       let w = window_rc.clone();
       context.register_uri_scheme(&name.clone(), move |request| {
         if let Some(uri) = request.get_uri() {
           let uri = uri.as_str();
-
-          match handler(&w, uri) {
-            Ok(buffer) => {
-              let mime = MimeType::parse(&buffer, uri);
-              let input = gio::MemoryInputStream::from_bytes(&Bytes::from(&buffer));
-              request.finish(&input, buffer.len() as i64, Some(&mime))
-            }
-            Err(_) => request.finish_error(&mut glib::Error::new(
-              FileError::Exist,
-              "Could not get requested file.",
-            )),
-          }
+          let response = handler(&w, &protocol::request(request, uri));
+          protocol::finish(request, uri, response);
         } else {
           request.finish_error(&mut glib::Error::new(
             FileError::Exist,
@@ -202,24 +281,55 @@ impl InnerWebView {
   }
 
   pub fn eval(&self, js: &str) -> Result<()> {
-    let cancellable: Option<&Cancellable> = None;
-    self.webview.run_javascript(js, cancellable, |_| ());
+    run_javascript(&self.webview, js, self.eval_errors.clone());
     Ok(())
   }
+}
 
-  fn init(&self, js: &str) -> Result<()> {
-    if let Some(manager) = self.webview.get_user_content_manager() {
-      let script = UserScript::new(
-        js,
-        UserContentInjectedFrames::TopFrame,
-        UserScriptInjectionTime::Start,
-        &[],
-        &[],
-      );
-      manager.add_script(&script);
-    } else {
-      return Err(Error::InitScriptError);
-    }
-    Ok(())
+// The keys of the script sending the RPC messages.
+struct DocumentKeys {
+  // Key of the script injected in the next document
+  next: String,
+  // Key and URI of the current document
+  current: Option<(String, String)>,
+}
+
+impl DocumentKeys {
+  // Get the URI of the document which sent a message and the message without its key, if the key
+  // is the one of the current document.
+  fn source<'a>(&'a self, message: &'a str) -> Option<(&'a str, &'a str)> {
+    let (key, uri) = self.current.as_ref()?;
+    let message = message.strip_prefix(key.as_str())?.strip_prefix(':')?;
+    Some((uri, message))
   }
 }
+
+// Add the initialization scripts to the top frame of the next documents, after the one sending the
+// messages with the key.
+fn add_scripts(manager: &UserContentManager, key: &str, scripts: &[String]) {
+  let external = format!(
+    "window.external={{invoke:function(x){{window.webkit.messageHandlers.external.postMessage({:?}+x);}}}}",
+    format!("{}:", key)
+  );
+  for js in std::iter::once(&external).chain(scripts) {
+    let script = UserScript::new(
+      js,
+      UserContentInjectedFrames::TopFrame,
+      UserScriptInjectionTime::Start,
+      &[],
+      &[],
+    );
+    manager.add_script(&script);
+  }
+}
+
+// Evaluate a script, sending the failures to the error handler since they are only known once the
+// evaluation completes.
+fn run_javascript(webview: &WebView, js: &str, errors: glib::Sender<String>) {
+  let cancellable: Option<&Cancellable> = None;
+  webview.run_javascript(js, cancellable, move |result| {
+    if let Err(e) = result {
+      let _ = errors.send(e.to_string());
+    }
+  });
+}
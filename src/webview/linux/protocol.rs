@@ -0,0 +1,221 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+// Conversions between the requests of the WebKitGTK URI schemes and the [`Request`] and
+// [`Response`] of the custom protocol handlers.
+//
+// The methods giving access to the HTTP method, headers and body of a request and the ones
+// answering it with a status and headers were only added in WebKitGTK 2.36 and 2.40, which the
+// bindings don't cover. They are looked up at runtime instead so older versions keep working,
+// only without those.
+
+use std::{
+  ffi::{CStr, CString},
+  mem,
+  os::raw::{c_char, c_int, c_uint, c_void},
+};
+
+use gio::{
+  prelude::InputStreamExtManual, Cancellable, InputStream, MemoryInputStream, ReadInputStream,
+};
+use glib::{translate::from_glib_full, Bytes, Cast, FileError, ObjectType};
+use once_cell::sync::Lazy;
+use webkit2gtk::{URISchemeRequest, URISchemeRequestExt};
+
+use crate::http::{Body, HeaderName, HeaderValue, Method, Request, Response};
+
+type GetHttpMethod = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type GetHttpHeaders = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type GetHttpBody = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type HeadersForeachFunc = unsafe extern "C" fn(*const c_char, *const c_char, *mut c_void);
+type HeadersForeach = unsafe extern "C" fn(*mut c_void, HeadersForeachFunc, *mut c_void);
+type HeadersNew = unsafe extern "C" fn(c_int) -> *mut c_void;
+type HeadersAppend = unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char);
+type ResponseNew = unsafe extern "C" fn(*mut c_void, i64) -> *mut c_void;
+type ResponseSetStatus = unsafe extern "C" fn(*mut c_void, c_uint, *const c_char);
+type ResponseSetContentType = unsafe extern "C" fn(*mut c_void, *const c_char);
+type ResponseSetHttpHeaders = unsafe extern "C" fn(*mut c_void, *mut c_void);
+type FinishWithResponse = unsafe extern "C" fn(*mut c_void, *mut c_void);
+
+// `SOUP_MESSAGE_HEADERS_RESPONSE`
+const SOUP_MESSAGE_HEADERS_RESPONSE: c_int = 1;
+
+// The functions of WebKitGTK and libsoup which may be missing from the loaded libraries.
+struct Symbols {
+  get_http_method: Option<GetHttpMethod>,
+  get_http_headers: Option<GetHttpHeaders>,
+  get_http_body: Option<GetHttpBody>,
+  headers_foreach: Option<HeadersForeach>,
+  headers_new: Option<HeadersNew>,
+  headers_append: Option<HeadersAppend>,
+  response_new: Option<ResponseNew>,
+  response_set_status: Option<ResponseSetStatus>,
+  response_set_content_type: Option<ResponseSetContentType>,
+  response_set_http_headers: Option<ResponseSetHttpHeaders>,
+  finish_with_response: Option<FinishWithResponse>,
+}
+
+static SYMBOLS: Lazy<Symbols> = Lazy::new(|| {
+  // Safety: the types match the signatures of the C functions
+  unsafe {
+    Symbols {
+      get_http_method: symbol(b"webkit_uri_scheme_request_get_http_method\0"),
+      get_http_headers: symbol(b"webkit_uri_scheme_request_get_http_headers\0"),
+      get_http_body: symbol(b"webkit_uri_scheme_request_get_http_body\0"),
+      headers_foreach: symbol(b"soup_message_headers_foreach\0"),
+      headers_new: symbol(b"soup_message_headers_new\0"),
+      headers_append: symbol(b"soup_message_headers_append\0"),
+      response_new: symbol(b"webkit_uri_scheme_response_new\0"),
+      response_set_status: symbol(b"webkit_uri_scheme_response_set_status\0"),
+      response_set_content_type: symbol(b"webkit_uri_scheme_response_set_content_type\0"),
+      response_set_http_headers: symbol(b"webkit_uri_scheme_response_set_http_headers\0"),
+      finish_with_response: symbol(b"webkit_uri_scheme_request_finish_with_response\0"),
+    }
+  }
+});
+
+// Look up a function of the libraries loaded in the process. `T` must be a function pointer type.
+unsafe fn symbol<T>(name: &[u8]) -> Option<T> {
+  let ptr = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const c_char);
+  if ptr.is_null() {
+    None
+  } else {
+    Some(mem::transmute_copy(&ptr))
+  }
+}
+
+/// Get the [`Request`] of the page from the request of a URI scheme.
+pub(super) fn request(request: &URISchemeRequest, uri: &str) -> Request {
+  let symbols = &*SYMBOLS;
+  let ptr = request.as_ptr() as *mut c_void;
+  let mut req = Request::new(uri);
+
+  // Safety: the pointers come from WebKitGTK, which keeps them alive as long as the request
+  unsafe {
+    if let Some(get_http_method) = symbols.get_http_method {
+      let method = get_http_method(ptr);
+      if !method.is_null() {
+        if let Ok(method) = Method::from_bytes(CStr::from_ptr(method).to_bytes()) {
+          req = req.with_method(method);
+        }
+      }
+    }
+
+    if let (Some(get_http_headers), Some(headers_foreach)) =
+      (symbols.get_http_headers, symbols.headers_foreach)
+    {
+      let headers = get_http_headers(ptr);
+      if !headers.is_null() {
+        unsafe extern "C" fn push(name: *const c_char, value: *const c_char, data: *mut c_void) {
+          let headers = &mut *(data as *mut Vec<(HeaderName, HeaderValue)>);
+          if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(CStr::from_ptr(name).to_bytes()),
+            HeaderValue::from_bytes(CStr::from_ptr(value).to_bytes()),
+          ) {
+            headers.push((name, value));
+          }
+        }
+        let mut list: Vec<(HeaderName, HeaderValue)> = Vec::new();
+        headers_foreach(headers, push, &mut list as *mut _ as *mut c_void);
+        for (name, value) in list {
+          req = req.with_header(name, value);
+        }
+      }
+    }
+
+    if let Some(get_http_body) = symbols.get_http_body {
+      let body = get_http_body(ptr);
+      if !body.is_null() {
+        let body: InputStream = from_glib_full(body as *mut <InputStream as ObjectType>::GlibType);
+        req = req.with_body(read_to_end(&body));
+      }
+    }
+  }
+
+  req
+}
+
+// Read a whole stream, stopping at the first error.
+fn read_to_end(stream: &InputStream) -> Vec<u8> {
+  let cancellable: Option<&Cancellable> = None;
+  let mut body = Vec::new();
+  let mut buffer = vec![0; 8192];
+  while let Ok(read) = stream.read(&mut buffer[..], cancellable) {
+    if read == 0 {
+      break;
+    }
+    body.extend_from_slice(&buffer[..read]);
+  }
+  body
+}
+
+/// Answer the request of a URI scheme with the [`Response`] of the handler.
+///
+/// Without the newer WebKitGTK methods, the status and the headers other than the content type
+/// can't be given. The request is answered with the body only if the status is successful and
+/// fails otherwise.
+pub(super) fn finish(request: &URISchemeRequest, uri: &str, response: Response) {
+  let symbols = &*SYMBOLS;
+  let mimetype = response.content_type(uri);
+  let length = response
+    .body()
+    .len()
+    .map(|length| length as i64)
+    .unwrap_or(-1);
+  let status = response.status();
+  let reason = response.reason();
+  let response_headers = response.headers().clone();
+  // Readers are read on the threads of GIO as the page consumes them
+  let input: InputStream = match response.into_body() {
+    Body::Bytes(bytes) => MemoryInputStream::from_bytes(&Bytes::from_owned(bytes)).upcast(),
+    Body::Reader(reader, _) => ReadInputStream::new(reader).upcast(),
+    Body::Seekable(reader, _) => ReadInputStream::new_seekable(reader).upcast(),
+  };
+
+  match (
+    symbols.response_new,
+    symbols.response_set_status,
+    symbols.response_set_content_type,
+    symbols.finish_with_response,
+  ) {
+    (
+      Some(response_new),
+      Some(response_set_status),
+      Some(response_set_content_type),
+      Some(finish_with_response),
+    ) => {
+      let reason = CString::new(reason).unwrap_or_default();
+      let mimetype = CString::new(mimetype).unwrap_or_default();
+      // Safety: the types match the signatures of the C functions and the response is released
+      // once given to the request, which keeps its own reference
+      unsafe {
+        let res = response_new(input.as_ptr() as *mut c_void, length);
+        response_set_status(res, status.as_u16() as c_uint, reason.as_ptr());
+        response_set_content_type(res, mimetype.as_ptr());
+        if let (Some(headers_new), Some(headers_append), Some(response_set_http_headers)) = (
+          symbols.headers_new,
+          symbols.headers_append,
+          symbols.response_set_http_headers,
+        ) {
+          // The response takes the ownership of the headers
+          let headers = headers_new(SOUP_MESSAGE_HEADERS_RESPONSE);
+          for (name, value) in &response_headers {
+            if let Ok(value) = CString::new(value.as_bytes()) {
+              let name = CString::new(name.as_str()).unwrap_or_default();
+              headers_append(headers, name.as_ptr(), value.as_ptr());
+            }
+          }
+          response_set_http_headers(res, headers);
+        }
+        finish_with_response(request.as_ptr() as *mut c_void, res);
+        glib::gobject_sys::g_object_unref(res as *mut _);
+      }
+    }
+    _ if status.is_success() => request.finish(&input, length, Some(&mimetype)),
+    _ => request.finish_error(&mut glib::Error::new(
+      FileError::Failed,
+      &format!("{} {}", status.as_u16(), reason),
+    )),
+  }
+}
@@ -3,14 +3,18 @@
 // SPDX-License-Identifier: MIT
 
 use std::{
+  cell::RefCell,
+  collections::HashSet,
   ffi::{c_void, CStr},
+  io::{self, Read},
   os::raw::c_char,
   path::PathBuf,
   ptr::null,
   rc::Rc,
-  slice, str,
+  slice, str, thread,
 };
 
+use block::ConcreteBlock;
 use cocoa::{
   appkit::{NSView, NSViewHeightSizable, NSViewWidthSizable},
   base::id,
@@ -27,8 +31,12 @@ use file_drop::{add_file_drop_methods, set_file_drop_handler};
 
 use crate::{
   application::{platform::macos::WindowExtMacOS, window::Window},
-  webview::{mimetype::MimeType, FileDropEvent, RpcRequest, RpcResponse},
-  Result,
+  http::{header, Body, HeaderName, HeaderValue, Method, Request, Response},
+  webview::{
+    rpc::RpcHandler, Dispatcher, ErrorHandler, FileDropEvent, Interception, PanicHook,
+    ProtocolOptions,
+  },
+  Error, Result,
 };
 
 mod file_drop;
@@ -36,8 +44,28 @@ mod file_drop;
 pub struct InnerWebView {
   webview: Id<Object>,
   manager: id,
+  window: Rc<Window>,
+  error_handler: Option<ErrorHandler>,
 }
 
+// State of the navigation delegate: the window and error handler the failed navigations are
+// reported to, and the dispatcher of the `call_js` calls and RPC bytes dropped by new pages along
+// with the panic hook of their callbacks.
+type NavigationState = (
+  Rc<Window>,
+  Option<ErrorHandler>,
+  Option<(Dispatcher, Option<PanicHook>)>,
+);
+
+// State of a URL scheme handler: the handler of the custom protocol, the window and error handler
+// it's called with, and the tasks whose body is still being read, which the page may stop.
+type SchemeState = (
+  Box<dyn Fn(&Window, &Request) -> Response>,
+  Rc<Window>,
+  Option<ErrorHandler>,
+  RefCell<HashSet<usize>>,
+);
+
 impl InnerWebView {
   pub fn new(
     window: Rc<Window>,
@@ -46,38 +74,51 @@ impl InnerWebView {
     transparent: bool,
     custom_protocols: Vec<(
       String,
-      Box<dyn Fn(&Window, &str) -> Result<Vec<u8>> + 'static>,
+      ProtocolOptions,
+      Box<dyn Fn(&Window, &Request) -> Response + 'static>,
     )>,
-    rpc_handler: Option<Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>>,
+    _request_interceptor: Option<Box<dyn Fn(&Window, &Request) -> Interception>>,
+    rpc_handler: Option<RpcHandler>,
     file_drop_handler: Option<Box<dyn Fn(&Window, FileDropEvent) -> bool>>,
     _data_directory: Option<PathBuf>,
+    error_handler: Option<ErrorHandler>,
   ) -> Result<Self> {
     // Function for rpc handler
     extern "C" fn did_receive(this: &Object, _: Sel, _: id, msg: id) {
       // Safety: objc runtime calls are unsafe
       unsafe {
         let function = this.get_ivar::<*mut c_void>("function");
-        let function = &mut *(*function
-          as *mut (
-            Box<dyn for<'r> Fn(&'r Window, RpcRequest) -> Option<RpcResponse>>,
-            Rc<Window>,
-          ));
+        let function = &mut *(*function as *mut (RpcHandler, Rc<Window>));
         let body: id = msg_send![msg, body];
         let utf8: *const c_char = msg_send![body, UTF8String];
         let js = CStr::from_ptr(utf8).to_str().expect("Invalid UTF8 string");
 
-        match super::rpc_proxy(&function.1, js.to_string(), &function.0) {
+        // URL of the frame which sent the message
+        let frame: id = msg_send![msg, frameInfo];
+        let request: id = msg_send![frame, request];
+        let url: id = msg_send![request, URL];
+        let source = if url.is_null() {
+          None
+        } else {
+          let s: id = msg_send![url, absoluteString];
+          Some(NSString(Id::from_ptr(s)))
+        };
+        let source = source.as_ref().map(|s| s.to_str());
+
+        let (rpc_handler, window) = (&function.0, &function.1);
+        match super::rpc_proxy(window, js.to_string(), source, rpc_handler) {
           Ok(result) => {
             if let Some(ref script) = result {
               let wv: id = msg_send![msg, webView];
-              let js = NSString::new(script);
-              let _: id =
-                msg_send![wv, evaluateJavaScript:js completionHandler:null::<*const c_void>()];
+              evaluate_javascript(
+                wv,
+                script,
+                window.clone(),
+                rpc_handler.error_handler.clone(),
+              );
             }
           }
-          Err(e) => {
-            eprintln!("{}", e);
-          }
+          Err(e) => super::report_error(rpc_handler.error_handler.as_ref(), window, e),
         }
       }
     }
@@ -86,11 +127,7 @@ impl InnerWebView {
     extern "C" fn start_task(this: &Object, _: Sel, _webview: id, task: id) {
       unsafe {
         let function = this.get_ivar::<*mut c_void>("function");
-        let function = &mut *(*function
-          as *mut (
-            Box<dyn for<'r, 's> Fn(&'r Window, &'s str) -> Result<Vec<u8>>>,
-            Rc<Window>,
-          ));
+        let function = &*(*function as *const SchemeState);
 
         // Get url request
         let request: id = msg_send![task, request];
@@ -102,31 +139,92 @@ impl InnerWebView {
         let uri = nsstring.to_str();
 
         // Send response
-        if let Ok(content) = function.0(&function.1, uri) {
-          let mime = MimeType::parse(&content, uri);
-          let nsurlresponse: id = msg_send![class!(NSURLResponse), alloc];
-          let response: id = msg_send![nsurlresponse, initWithURL:url MIMEType:NSString::new(&mime)
-                        expectedContentLength:content.len() textEncodingName:null::<c_void>()];
-          let () = msg_send![task, didReceiveResponse: response];
-
-          // Send data
-          let bytes = content.as_ptr() as *mut c_void;
-          let data: id = msg_send![class!(NSData), alloc];
-          let data: id = msg_send![data, initWithBytes:bytes length:content.len()];
-          let () = msg_send![task, didReceiveData: data];
-
-          // Finish
-          let () = msg_send![task, didFinish];
+        let response = function.0(&function.1, &get_request(request, uri));
+        let headers = get_headers(&response, uri);
+        let nsurlresponse: id = msg_send![class!(NSHTTPURLResponse), alloc];
+        let nsurlresponse: id = msg_send![nsurlresponse, initWithURL:url
+                    statusCode:response.status().as_u16() as isize
+                    HTTPVersion:NSString::new("HTTP/1.1") headerFields:headers];
+        let () = msg_send![task, didReceiveResponse: nsurlresponse];
+
+        // Send data
+        let mut reader: Box<dyn Read + Send> = match response.into_body() {
+          Body::Bytes(bytes) => {
+            send_data(task, &bytes);
+            let () = msg_send![task, didFinish];
+            return;
+          }
+          Body::Reader(reader, _) => reader,
+          Body::Seekable(reader, _) => Box::new(reader),
+        };
+
+        // Read the other bodies on another thread so a slow reader doesn't block the main thread,
+        // sending each chunk from the main thread until the page stops the task. The task is
+        // retained until then, so its address identifies it.
+        let _: id = msg_send![task, retain];
+        function.3.borrow_mut().insert(task as usize);
+        let (state, task) = (function as *const SchemeState as usize, task as usize);
+        thread::spawn(move || {
+          let result = read_chunks(&mut reader, |chunk| {
+            run_on_main(|| send_chunk(&*(state as *const SchemeState), task as id, chunk))
+          });
+          run_on_main(|| finish_task(&*(state as *const SchemeState), task as id, result));
+        });
+      }
+    }
+
+    // Forget the tasks the page gave up on, which can't receive data anymore
+    extern "C" fn stop_task(this: &Object, _: Sel, _webview: id, task: id) {
+      unsafe {
+        let function = this.get_ivar::<*mut c_void>("function");
+        let function = &*(*function as *const SchemeState);
+        function.3.borrow_mut().remove(&(task as usize));
+      }
+    }
+
+    // Navigation delegate reporting the pages which fail to load
+    extern "C" fn did_fail_navigation(
+      this: &Object,
+      _: Sel,
+      webview: id,
+      _navigation: id,
+      error: id,
+    ) {
+      // Safety: objc runtime calls are unsafe
+      unsafe {
+        let function = this.get_ivar::<*mut c_void>("function");
+        let function = &mut *(*function as *mut NavigationState);
+        let url: id = msg_send![webview, URL];
+        let uri = if url.is_null() {
+          String::new()
+        } else {
+          let s: id = msg_send![url, absoluteString];
+          NSString(Id::from_ptr(s)).to_str().to_string()
+        };
+        let description: id = msg_send![error, localizedDescription];
+        let description = NSString(Id::from_ptr(description));
+        let error = Error::NavigationError(uri, description.to_str().to_string());
+        super::report_error(function.1.as_ref(), &function.0, error);
+      }
+    }
+
+    // Navigation delegate dropping the `call_js` calls and the RPC bytes of the previous page
+    extern "C" fn did_commit_navigation(this: &Object, _: Sel, _webview: id, _navigation: id) {
+      // Safety: objc runtime calls are unsafe
+      unsafe {
+        let function = this.get_ivar::<*mut c_void>("function");
+        let function = &mut *(*function as *mut NavigationState);
+        if let Some((dispatcher, panic_hook)) = &function.2 {
+          dispatcher.navigated(panic_hook.as_ref());
         }
       }
     }
-    extern "C" fn stop_task(_: &Object, _: Sel, _webview: id, _task: id) {}
 
     // Safety: objc runtime calls are unsafe
     unsafe {
       // Config and custom protocol
       let config: id = msg_send![class!(WKWebViewConfiguration), new];
-      for (name, function) in custom_protocols {
+      for (name, _, function) in custom_protocols {
         let scheme_name = format!("{}URLSchemeHandler", name);
         let cls = ClassDecl::new(&scheme_name, class!(NSObject));
         let cls = match cls {
@@ -146,8 +244,12 @@ impl InnerWebView {
         };
         let handler: id = msg_send![cls, new];
         let w = window.clone();
-        let function: Box<(Box<dyn Fn(&Window, &str) -> Result<Vec<u8>>>, Rc<Window>)> =
-          Box::new((Box::new(function), w));
+        let function: Box<SchemeState> = Box::new((
+          Box::new(function),
+          w,
+          error_handler.clone(),
+          RefCell::new(HashSet::new()),
+        ));
 
         (*handler).set_ivar("function", Box::into_raw(function) as *mut _ as *mut c_void);
         let () = msg_send![config, setURLSchemeHandler:handler forURLScheme:NSString::new(&name)];
@@ -190,6 +292,12 @@ impl InnerWebView {
       webview.setAutoresizingMask_(NSViewHeightSizable | NSViewWidthSizable);
 
       // Message handler
+      let dispatcher = rpc_handler.as_ref().map(|rpc_handler| {
+        (
+          rpc_handler.dispatcher.clone(),
+          rpc_handler.panic_hook.clone(),
+        )
+      });
       if let Some(rpc_handler) = rpc_handler {
         let cls = ClassDecl::new("WebViewDelegate", class!(NSObject));
         let cls = match cls {
@@ -211,6 +319,32 @@ impl InnerWebView {
         let _: () = msg_send![manager, addScriptMessageHandler:handler name:external];
       }
 
+      // Navigation delegate
+      let cls = match ClassDecl::new("WryNavigationDelegate", class!(NSObject)) {
+        Some(mut cls) => {
+          cls.add_ivar::<*mut c_void>("function");
+          cls.add_method(
+            sel!(webView:didFailNavigation:withError:),
+            did_fail_navigation as extern "C" fn(&Object, Sel, id, id, id),
+          );
+          cls.add_method(
+            sel!(webView:didFailProvisionalNavigation:withError:),
+            did_fail_navigation as extern "C" fn(&Object, Sel, id, id, id),
+          );
+          cls.add_method(
+            sel!(webView:didCommitNavigation:),
+            did_commit_navigation as extern "C" fn(&Object, Sel, id, id),
+          );
+          cls.register()
+        }
+        None => class!(WryNavigationDelegate),
+      };
+      let delegate: id = msg_send![cls, new];
+      let function: Box<NavigationState> =
+        Box::new((window.clone(), error_handler.clone(), dispatcher));
+      (*delegate).set_ivar("function", Box::into_raw(function) as *mut _ as *mut c_void);
+      let () = msg_send![webview, setNavigationDelegate: delegate];
+
       // File drop handling
       match file_drop_handler {
         // if we have a file_drop_handler defined, use the defined handler
@@ -224,6 +358,8 @@ impl InnerWebView {
       let w = Self {
         webview: Id::from_ptr(webview),
         manager,
+        window: window.clone(),
+        error_handler,
       };
 
       // Initialize scripts
@@ -284,10 +420,12 @@ impl InnerWebView {
   }
 
   pub fn eval(&self, js: &str) -> Result<()> {
-    // Safety: objc runtime calls are unsafe
-    unsafe {
-      let _: id = msg_send![self.webview, evaluateJavaScript:NSString::new(js) completionHandler:null::<*const c_void>()];
-    }
+    evaluate_javascript(
+      &*self.webview as *const Object as id,
+      js,
+      self.window.clone(),
+      self.error_handler.clone(),
+    );
     Ok(())
   }
 
@@ -321,6 +459,179 @@ impl InnerWebView {
   }
 }
 
+// Get the [`Request`] of the page from an `NSURLRequest`.
+unsafe fn get_request(request: id, uri: &str) -> Request {
+  let mut req = Request::new(uri);
+
+  let method: id = msg_send![request, HTTPMethod];
+  if !method.is_null() {
+    let method = NSString(Id::from_ptr(method));
+    if let Ok(method) = Method::from_bytes(method.to_str().as_bytes()) {
+      req = req.with_method(method);
+    }
+  }
+
+  let headers: id = msg_send![request, allHTTPHeaderFields];
+  if !headers.is_null() {
+    let keys: id = msg_send![headers, allKeys];
+    let count: usize = msg_send![keys, count];
+    for i in 0..count {
+      let key: id = msg_send![keys, objectAtIndex: i];
+      let value: id = msg_send![headers, objectForKey: key];
+      let (key, value) = (NSString(Id::from_ptr(key)), NSString(Id::from_ptr(value)));
+      if let (Ok(name), Ok(value)) = (
+        HeaderName::from_bytes(key.to_str().as_bytes()),
+        HeaderValue::from_str(value.to_str()),
+      ) {
+        req = req.with_header(name, value);
+      }
+    }
+  }
+
+  let body: id = msg_send![request, HTTPBody];
+  if !body.is_null() {
+    let length: usize = msg_send![body, length];
+    let bytes: *const u8 = msg_send![body, bytes];
+    req = req.with_body(slice::from_raw_parts(bytes, length).to_vec());
+  }
+
+  req
+}
+
+// Get the header fields of an `NSHTTPURLResponse` from a [`Response`]. A dictionary holds a single
+// value by name, so the values of a repeated header are joined.
+unsafe fn get_headers(response: &Response, uri: &str) -> id {
+  let headers: id = msg_send![class!(NSMutableDictionary), dictionary];
+  let mimetype = NSString::new(&response.content_type(uri));
+  let () = msg_send![headers, setObject:mimetype forKey:NSString::new("Content-Type")];
+  for name in response.headers().keys() {
+    if name != header::CONTENT_TYPE {
+      let value = response
+        .headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ");
+      let () =
+        msg_send![headers, setObject:NSString::new(&value) forKey:NSString::new(name.as_str())];
+    }
+  }
+  headers
+}
+
+// Read a body in chunks until its end, or until `send` returns `false`.
+fn read_chunks<F: FnMut(&[u8]) -> bool>(reader: &mut dyn Read, mut send: F) -> io::Result<()> {
+  let mut buffer = vec![0; 64 * 1024];
+  loop {
+    match reader.read(&mut buffer) {
+      Ok(0) => return Ok(()),
+      Ok(read) => {
+        if !send(&buffer[..read]) {
+          return Ok(());
+        }
+      }
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+// Send a chunk of a body read on another thread to its task, returning whether the task is still
+// running.
+unsafe fn send_chunk(state: &SchemeState, task: id, chunk: &[u8]) -> bool {
+  let running = state.3.borrow().contains(&(task as usize));
+  if running {
+    send_data(task, chunk);
+  }
+  running
+}
+
+// Complete a task once its body is read, unless the page stopped it, and release it.
+unsafe fn finish_task(state: &SchemeState, task: id, result: io::Result<()>) {
+  if state.3.borrow_mut().remove(&(task as usize)) {
+    match result {
+      Ok(()) => {
+        let () = msg_send![task, didFinish];
+      }
+      Err(e) => {
+        super::report_error(state.2.as_ref(), &state.1, e.into());
+        let error: id = msg_send![class!(NSError), errorWithDomain:NSString::new("wry") code:0 userInfo:null::<c_void>()];
+        let () = msg_send![task, didFailWithError: error];
+      }
+    }
+  }
+  let () = msg_send![task, release];
+}
+
+unsafe fn send_data(task: id, bytes: &[u8]) {
+  let data: id = msg_send![class!(NSData), dataWithBytes:bytes.as_ptr() length:bytes.len()];
+  let () = msg_send![task, didReceiveData: data];
+}
+
+#[repr(C)]
+struct DispatchQueue {
+  _private: [u8; 0],
+}
+
+extern "C" {
+  // The main queue of libdispatch, which `dispatch_get_main_queue()` returns
+  static _dispatch_main_q: DispatchQueue;
+  fn dispatch_sync_f(
+    queue: *const DispatchQueue,
+    context: *mut c_void,
+    work: extern "C" fn(*mut c_void),
+  );
+}
+
+// Run a closure on the main thread from another thread, waiting for its result.
+fn run_on_main<R, F: FnOnce() -> R>(f: F) -> R {
+  extern "C" fn work<R, F: FnOnce() -> R>(context: *mut c_void) {
+    // Safety: the context is the one below, which lives until `dispatch_sync_f` returns
+    let context = unsafe { &mut *(context as *mut (Option<F>, Option<R>)) };
+    if let Some(f) = context.0.take() {
+      context.1 = Some(f());
+    }
+  }
+  let mut context: (Option<F>, Option<R>) = (Some(f), None);
+  // Safety: libdispatch calls are unsafe
+  unsafe {
+    dispatch_sync_f(
+      &_dispatch_main_q,
+      &mut context as *mut _ as *mut c_void,
+      work::<R, F>,
+    );
+  }
+  context.1.expect("the work ran on the main thread")
+}
+
+// Evaluate a script, passing the failures to the error handler since they are only known once the
+// evaluation completes.
+fn evaluate_javascript(
+  webview: id,
+  js: &str,
+  window: Rc<Window>,
+  error_handler: Option<ErrorHandler>,
+) {
+  let handler = ConcreteBlock::new(move |_result: id, error: id| {
+    if !error.is_null() {
+      // Safety: objc runtime calls are unsafe
+      let description = unsafe {
+        let description: id = msg_send![error, localizedDescription];
+        NSString(Id::from_ptr(description))
+      };
+      let error = Error::EvalError(description.to_str().to_string());
+      super::report_error(error_handler.as_ref(), &window, error);
+    }
+  });
+  let handler = handler.copy();
+  // Safety: objc runtime calls are unsafe
+  unsafe {
+    let _: id =
+      msg_send![webview, evaluateJavaScript:NSString::new(js) completionHandler:&*handler];
+  }
+}
+
 const UTF8_ENCODING: usize = 4;
 
 struct NSString(Id<Object>);
@@ -6,64 +6,165 @@ use std::fmt;
 
 const MIMETYPE_PLAIN: &str = "text/plain";
 
+/// The content types of the file extensions, from the
+/// [common types](https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types)
+/// of the web.
+const EXTENSIONS: &[(&str, &str)] = &[
+  // Documents
+  ("htm", "text/html"),
+  ("html", "text/html"),
+  ("xhtml", "application/xhtml+xml"),
+  ("css", "text/css"),
+  ("csv", "text/csv"),
+  ("ics", "text/calendar"),
+  ("md", "text/markdown"),
+  ("txt", "text/plain"),
+  ("vtt", "text/vtt"),
+  ("xml", "application/xml"),
+  ("rtf", "application/rtf"),
+  ("pdf", "application/pdf"),
+  // Scripts and data
+  ("js", "text/javascript"),
+  ("mjs", "text/javascript"),
+  ("cjs", "text/javascript"),
+  ("json", "application/json"),
+  ("map", "application/json"),
+  ("jsonld", "application/ld+json"),
+  ("webmanifest", "application/manifest+json"),
+  ("wasm", "application/wasm"),
+  ("toml", "application/toml"),
+  ("yaml", "application/yaml"),
+  ("yml", "application/yaml"),
+  // Images
+  ("apng", "image/apng"),
+  ("avif", "image/avif"),
+  ("bmp", "image/bmp"),
+  ("gif", "image/gif"),
+  ("ico", "image/vnd.microsoft.icon"),
+  ("jpeg", "image/jpeg"),
+  ("jpg", "image/jpeg"),
+  ("png", "image/png"),
+  ("svg", "image/svg+xml"),
+  ("tif", "image/tiff"),
+  ("tiff", "image/tiff"),
+  ("webp", "image/webp"),
+  // Fonts
+  ("eot", "application/vnd.ms-fontobject"),
+  ("otf", "font/otf"),
+  ("ttf", "font/ttf"),
+  ("woff", "font/woff"),
+  ("woff2", "font/woff2"),
+  // Audio
+  ("aac", "audio/aac"),
+  ("flac", "audio/flac"),
+  ("m4a", "audio/mp4"),
+  ("mid", "audio/midi"),
+  ("midi", "audio/midi"),
+  ("mp3", "audio/mpeg"),
+  ("oga", "audio/ogg"),
+  ("ogg", "audio/ogg"),
+  ("opus", "audio/opus"),
+  ("wav", "audio/wav"),
+  ("weba", "audio/webm"),
+  // Video
+  ("avi", "video/x-msvideo"),
+  ("m4v", "video/mp4"),
+  ("mov", "video/quicktime"),
+  ("mp4", "video/mp4"),
+  ("mpeg", "video/mpeg"),
+  ("ogv", "video/ogg"),
+  ("webm", "video/webm"),
+  // 3D models
+  ("glb", "model/gltf-binary"),
+  ("gltf", "model/gltf+json"),
+  // Archives and binaries
+  ("bin", "application/octet-stream"),
+  ("gz", "application/gzip"),
+  ("tar", "application/x-tar"),
+  ("zip", "application/zip"),
+];
+
 /// [Web Compatible MimeTypes](https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types#important_mime_types_for_web_developers)
-pub(crate) enum MimeType {
-  CSS,
-  CSV,
-  HTML,
-  ICO,
-  JS,
-  JSON,
-  JSONLD,
-  OCTETSTREAM,
-  RTF,
-  SVG,
-}
+///
+/// It's displayed with a `charset=utf-8` parameter for the text types, like
+/// `text/html; charset=utf-8`, and can be used by the custom protocol handlers to set the content
+/// type of their responses:
+///
+/// ```
+/// use wry::{http::Response, webview::MimeType};
+///
+/// let mimetype = MimeType::parse_from_uri("app://assets/main.mjs?v=2");
+/// assert_eq!(mimetype, MimeType::JS);
+/// let response = Response::new(b"export {}".to_vec()).with_mimetype(&mimetype.to_string());
+/// assert_eq!(response.mimetype(), Some("text/javascript; charset=utf-8"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MimeType(&'static str);
 
-impl std::fmt::Display for MimeType {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    let mime = match self {
-      MimeType::CSS => "text/css",
-      MimeType::CSV => "text/csv",
-      MimeType::HTML => "text/html",
-      MimeType::ICO => "image/vnd.microsoft.icon",
-      MimeType::JS => "text/javascript",
-      MimeType::JSON => "application/json",
-      MimeType::JSONLD => "application/ld+json",
-      MimeType::OCTETSTREAM => "application/octet-stream",
-      MimeType::RTF => "application/rtf",
-      MimeType::SVG => "image/svg+xml",
-    };
-    write!(f, "{}", mime)
+impl MimeType {
+  pub const CSS: MimeType = MimeType("text/css");
+  pub const CSV: MimeType = MimeType("text/csv");
+  pub const HTML: MimeType = MimeType("text/html");
+  pub const ICO: MimeType = MimeType("image/vnd.microsoft.icon");
+  pub const JS: MimeType = MimeType("text/javascript");
+  pub const JSON: MimeType = MimeType("application/json");
+  pub const JSONLD: MimeType = MimeType("application/ld+json");
+  pub const OCTETSTREAM: MimeType = MimeType("application/octet-stream");
+  pub const PLAIN: MimeType = MimeType(MIMETYPE_PLAIN);
+  pub const RTF: MimeType = MimeType("application/rtf");
+  pub const SVG: MimeType = MimeType("image/svg+xml");
+  pub const WASM: MimeType = MimeType("application/wasm");
+
+  /// The content type of a file extension, like `woff2`, ignoring its case.
+  pub fn from_extension(extension: &str) -> Option<MimeType> {
+    let extension = extension.to_ascii_lowercase();
+    EXTENSIONS
+      .iter()
+      .find(|(ext, _)| *ext == extension)
+      .map(|(_, mime)| MimeType(mime))
+  }
+
+  /// The type without parameters, like `text/html`.
+  pub fn essence(&self) -> &'static str {
+    self.0
+  }
+
+  /// Whether it's a text type, which is displayed with a charset.
+  pub fn is_text(&self) -> bool {
+    let mime = self.0;
+    mime.starts_with("text/")
+      || mime.ends_with("+json")
+      || mime.ends_with("+xml")
+      || matches!(
+        mime,
+        "application/json" | "application/xml" | "application/toml" | "application/yaml"
+      )
   }
-}
 
-impl MimeType {
   /// parse a URI suffix to convert text/plain mimeType to their actual web compatible mimeType.
+  ///
+  /// The query and fragment of the URI are ignored. A path without extension, like the routes of
+  /// a single page application, is assumed to be HTML, and an unknown extension binary.
   pub fn parse_from_uri(uri: &str) -> MimeType {
-    let suffix = uri.split('.').last();
-    match suffix {
-      Some("bin") => Self::OCTETSTREAM,
-      Some("css") => Self::CSS,
-      Some("csv") => Self::CSV,
-      Some("html") => Self::HTML,
-      Some("ico") => Self::ICO,
-      Some("js") => Self::JS,
-      Some("json") => Self::JSON,
-      Some("jsonld") => Self::JSONLD,
-      Some("rtf") => Self::RTF,
-      Some("svg") => Self::SVG,
-      // Assume HTML when a TLD is found for eg. `wry:://tauri.studio` | `wry://hello.com`
-      Some(_) => Self::HTML,
-      // https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
-      // using octet stream according to this:
-      None => Self::OCTETSTREAM,
+    let uri = uri.split(&['?', '#'][..]).next().unwrap_or_default();
+    let path = uri.splitn(2, "://").last().unwrap_or_default();
+    let name = path.rsplit(&['/', '\\'][..]).next().unwrap_or_default();
+    match name.rfind('.') {
+      Some(dot) => match Self::from_extension(&name[dot + 1..]) {
+        Some(mime) => mime,
+        // Assume HTML when a TLD is found for eg. `wry:://tauri.studio` | `wry://hello.com`
+        None if !path.contains('/') => Self::HTML,
+        // https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
+        // using octet stream according to this:
+        None => Self::OCTETSTREAM,
+      },
+      None => Self::HTML,
     }
   }
 
   /// infer mimetype from content (or) URI if needed.
   pub fn parse(content: &[u8], uri: &str) -> String {
-    let mime = match infer::get(&content) {
+    let mime = match infer::get(content) {
       Some(info) => info.mime_type(),
       None => MIMETYPE_PLAIN,
     };
@@ -76,6 +177,16 @@ impl MimeType {
   }
 }
 
+impl fmt::Display for MimeType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_text() {
+      write!(f, "{}; charset=utf-8", self.0)
+    } else {
+      write!(f, "{}", self.0)
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -86,37 +197,58 @@ mod tests {
       "https://unpkg.com/browse/bootstrap@4.1.0/dist/css/bootstrap-grid.css",
     )
     .to_string();
-    assert_eq!(css, "text/css".to_string());
+    assert_eq!(css, "text/css; charset=utf-8".to_string());
 
     let csv: String = MimeType::parse_from_uri("https://example.com/random.csv").to_string();
-    assert_eq!(csv, "text/csv".to_string());
+    assert_eq!(csv, "text/csv; charset=utf-8".to_string());
 
     let ico: String =
       MimeType::parse_from_uri("https://icons.duckduckgo.com/ip3/microsoft.com.ico").to_string();
     assert_eq!(ico, String::from("image/vnd.microsoft.icon"));
 
     let html: String = MimeType::parse_from_uri("https://tauri.studio/index.html").to_string();
-    assert_eq!(html, String::from("text/html"));
+    assert_eq!(html, String::from("text/html; charset=utf-8"));
 
     let js: String =
       MimeType::parse_from_uri("https://unpkg.com/react@17.0.1/umd/react.production.min.js")
         .to_string();
-    assert_eq!(js, "text/javascript".to_string());
+    assert_eq!(js, "text/javascript; charset=utf-8".to_string());
 
     let json: String =
       MimeType::parse_from_uri("https://unpkg.com/browse/react@17.0.1/build-info.json").to_string();
-    assert_eq!(json, String::from("application/json"));
+    assert_eq!(json, String::from("application/json; charset=utf-8"));
 
     let jsonld: String = MimeType::parse_from_uri("https:/example.com/hello.jsonld").to_string();
-    assert_eq!(jsonld, String::from("application/ld+json"));
+    assert_eq!(jsonld, String::from("application/ld+json; charset=utf-8"));
 
     let rtf: String = MimeType::parse_from_uri("https://example.com/document.rtf").to_string();
     assert_eq!(rtf, String::from("application/rtf"));
 
     let svg: String = MimeType::parse_from_uri("https://example.com/picture.svg").to_string();
-    assert_eq!(svg, String::from("image/svg+xml"));
+    assert_eq!(svg, String::from("image/svg+xml; charset=utf-8"));
 
     let custom_scheme = MimeType::parse_from_uri("wry://tauri.studio").to_string();
-    assert_eq!(custom_scheme, String::from("text/html"));
+    assert_eq!(custom_scheme, String::from("text/html; charset=utf-8"));
+  }
+
+  #[test]
+  fn should_parse_web_formats_from_uri() {
+    let cases = [
+      ("wry://app/main.wasm", "application/wasm"),
+      ("wry://app/module.mjs", "text/javascript"),
+      ("wry://app/fonts/inter.woff2", "font/woff2"),
+      ("wry://app/images/photo.WEBP", "image/webp"),
+      ("wry://app/video.mp4", "video/mp4"),
+      ("wry://app/LICENSE.txt", "text/plain"),
+      ("wry://app/sitemap.xml", "application/xml"),
+      ("wry://app/main.js.map", "application/json"),
+      ("wry://app/main.js?v=1.2.3", "text/javascript"),
+      ("wry://app/style.css#section.title", "text/css"),
+      ("wry://app/about", "text/html"),
+      ("wry://app/data.unknown", "application/octet-stream"),
+    ];
+    for (uri, mime) in cases.iter() {
+      assert_eq!(MimeType::parse_from_uri(uri).essence(), *mime, "{}", uri);
+    }
   }
 }
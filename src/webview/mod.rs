@@ -4,7 +4,29 @@
 
 //! [`WebView`] struct and associated types.
 
-mod mimetype;
+#[cfg(feature = "protocol")]
+mod asset;
+#[cfg(feature = "protocol")]
+mod embed;
+pub(crate) mod mimetype;
+mod rpc;
+mod typescript;
+
+#[cfg(feature = "protocol")]
+pub use asset::AssetProtocol;
+#[cfg(feature = "protocol")]
+pub use embed::{EmbeddedAsset, EmbeddedAssets};
+pub use mimetype::MimeType;
+#[cfg(feature = "msgpack")]
+pub use rpc::MessagePackCodec;
+pub use rpc::{
+  CancellationToken, RpcBytes, RpcCodec, RpcError, RpcNext, RpcRequest, RpcResponder, RpcResponse,
+  RpcRouter, RpcSink, TypedRpcSink,
+};
+use rpc::{JsCalls, RpcAcl, RpcBytesStore, RpcHandler, RpcInterceptor};
+pub use typescript::TsType;
+#[cfg(feature = "derive")]
+pub use wry_macros::TsType;
 
 #[cfg(target_os = "linux")]
 mod linux;
@@ -27,14 +49,25 @@ mod win32;
 #[cfg(feature = "win32")]
 use win32::*;
 
-use crate::{Error, Result};
+use crate::{
+  http::{Request, Response, StatusCode},
+  Error, Result,
+};
 
 use std::{
+  any::Any,
+  fmt,
+  panic::{self, AssertUnwindSafe},
   path::PathBuf,
   rc::Rc,
-  sync::mpsc::{channel, Receiver, Sender},
+  sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+  time::Duration,
 };
 
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use url::Url;
 
@@ -45,37 +78,295 @@ use windows_webview2::Windows::Win32::WindowsAndMessaging::HWND;
 #[cfg(target_os = "windows")]
 use winit::platform::windows::WindowExtWindows;
 
-// Helper so all platforms handle RPC messages consistently.
+// Helper so all platforms handle RPC messages consistently. `source` is the URL of the page which
+// sent the message.
 fn rpc_proxy(
   window: &Window,
   js: String,
-  handler: &dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>,
+  source: Option<&str>,
+  handler: &RpcHandler,
 ) -> Result<Option<String>> {
-  let req = serde_json::from_str::<RpcRequest>(&js)
-    .map_err(|e| Error::RpcScriptError(e.to_string(), js))?;
-
-  let mut response = (handler)(window, req);
-  // Got a synchronous response so convert it to a script to be evaluated
-  if let Some(mut response) = response.take() {
-    if let Some(id) = response.id {
-      let js = if let Some(error) = response.error.take() {
-        RpcResponse::get_error_script(id, error)?
-      } else if let Some(result) = response.result.take() {
-        RpcResponse::get_result_script(id, result)?
+  let origin = source.and_then(rpc::origin_of);
+  let codec = handler.dispatcher.codec.as_deref();
+  let bytes = &handler.dispatcher.bytes;
+
+  let message = match rpc::unpack_message(codec, bytes, &js) {
+    Some(message) => message?,
+    None => match serde_json::from_str::<Value>(&js) {
+      Ok(message) => message,
+      Err(e) => {
+        // Reply with an error if we can tell which call the message belongs to, so the promise
+        // on the Javascript side doesn't wait forever.
+        return match RpcError::from_message(&js, &e) {
+          Some((id, error)) => Ok(Some(RpcResponse::error_script(codec, bytes, &id, &error)?)),
+          None => Err(Error::RpcScriptError(e.to_string(), js)),
+        };
+      }
+    },
+  };
+
+  match message {
+    Value::Array(batch) => {
+      // Like any request which isn't an object, an empty batch is answered with an error without
+      // an id
+      if batch.is_empty() {
+        let error = RpcError::invalid_request("Empty batch");
+        return Ok(Some(RpcResponse::error_script(
+          codec,
+          bytes,
+          &Value::Null,
+          &error,
+        )?));
+      }
+      // Every request of the batch goes through the handler but all the synchronous responses
+      // are sent back together in a single script.
+      let mut responses = Vec::new();
+      for message in batch {
+        if !message.is_object() {
+          let error = RpcError::invalid_request(format!("Not a request object: {}", message));
+          responses.push(RpcResponse::new_error(Some(Value::Null), error));
+          continue;
+        }
+        match catch_rpc_request(window, message, origin.as_deref(), handler) {
+          Ok(Some(response)) => responses.push(response),
+          Ok(None) => (),
+          Err(e) => report_error(handler.error_handler.as_ref(), window, e),
+        }
+      }
+      if responses.is_empty() {
+        Ok(None)
       } else {
-        // No error or result, assume a positive response
-        // with empty result (ACK)
-        RpcResponse::get_result_script(id, Value::Null)?
+        Ok(Some(RpcResponse::get_batch_script(
+          codec, bytes, responses,
+        )?))
+      }
+    }
+    message => match catch_rpc_request(window, message, origin.as_deref(), handler)? {
+      Some(response) => response.into_script(codec, bytes),
+      None => Ok(None),
+    },
+  }
+}
+
+// Same as `rpc_request`, catching the panics of the handler so they don't unwind through the
+// callbacks of the web engine. A request which panicked is answered with an internal error.
+fn catch_rpc_request(
+  window: &Window,
+  message: Value,
+  origin: Option<&str>,
+  handler: &RpcHandler,
+) -> Result<Option<RpcResponse>> {
+  let id = message.get("id").filter(|id| rpc::is_valid_id(id)).cloned();
+  let method = message
+    .get("method")
+    .and_then(Value::as_str)
+    .map(String::from);
+  panic::catch_unwind(AssertUnwindSafe(|| {
+    rpc_request(window, message, origin, handler)
+  }))
+  .unwrap_or_else(|payload| {
+    // Only requests get an error back, not the replies to `call_js`.
+    let response = id
+      .filter(|_| method.is_some())
+      .map(|id| RpcResponse::new_error(Some(id), RpcError::internal_error("The handler panicked")));
+    let panic = HandlerPanic::Rpc {
+      method,
+      message: panic_message(&*payload),
+    };
+    report_panic(handler.panic_hook.as_ref(), &panic);
+    Ok(response)
+  })
+}
+
+// Pass a single request to the handler, returning its synchronous response if any.
+fn rpc_request(
+  window: &Window,
+  mut message: Value,
+  origin: Option<&str>,
+  handler: &RpcHandler,
+) -> Result<Option<RpcResponse>> {
+  // Replies to `call_js` and cancellations carry no method to check, the page only needs to be
+  // allowed at all to send them. Either way the origin is checked before anything is done.
+  let method = message
+    .get("method")
+    .and_then(Value::as_str)
+    .map(String::from);
+  let allowed = match method.as_deref() {
+    Some(method) if method != rpc::CANCEL_METHOD => handler.acl.is_allowed(origin, method),
+    _ => handler.acl.allows_origin(origin),
+  };
+  if !allowed {
+    log::warn!(
+      "RPC {} isn't allowed for origin {}",
+      method
+        .as_ref()
+        .map_or("message".into(), |method| format!("method {}", method)),
+      origin.unwrap_or("null")
+    );
+    let error = RpcError::new(
+      RpcError::PERMISSION_DENIED,
+      format!(
+        "Method not allowed: {}",
+        method.as_deref().unwrap_or_default()
+      ),
+    );
+    return Ok(
+      method
+        .as_ref()
+        .and(message.get("id"))
+        .filter(|id| rpc::is_valid_id(id))
+        .map(|id| RpcResponse::new_error(Some(id.clone()), error)),
+    );
+  }
+  // The `RpcBytes` of the message can only be the ones its page uploaded
+  handler.dispatcher.bytes.resolve(&mut message);
+
+  // A reply to a call made with `call_js` rather than a request
+  if method.is_none() && (message.get("result").is_some() || message.get("error").is_some()) {
+    handler.dispatcher.js_calls.settle(
+      RpcResponse::deserialize(&message)?,
+      handler.panic_hook.as_ref(),
+    );
+    return Ok(None);
+  }
+
+  // The page gave up on a call, see `CancellationToken`
+  if method.as_deref() == Some(rpc::CANCEL_METHOD) {
+    if let Some(id) = message.get("params").and_then(|params| params.get("id")) {
+      handler.cancellations.cancel(id);
+      handler.dispatcher.bytes.release(id);
+    }
+    return Ok(None);
+  }
+
+  let mut req = match RpcRequest::deserialize(&message) {
+    Ok(req) => req,
+    Err(e) => {
+      return match message.get("id").filter(|id| rpc::is_valid_id(id)) {
+        Some(id) => Ok(Some(RpcResponse::new_error(
+          Some(id.clone()),
+          RpcError::invalid_request(e.to_string()),
+        ))),
+        None => Err(Error::RpcScriptError(e.to_string(), message.to_string())),
       };
-      Ok(Some(js))
-    } else {
-      Ok(None)
     }
+  };
+  req.origin = origin.map(Into::into);
+  req.responder = req.id.clone().map(|id| {
+    let cancelled = handler.cancellations.register(&id);
+    RpcResponder::new(id, handler.dispatcher.clone(), cancelled)
+  });
+
+  Ok(handler.handle(window, req))
+}
+
+/// A panic caught in a handler, passed to the hook set with [`WebViewBuilder::with_panic_hook`].
+///
+/// The handlers run inside callbacks of the web engine, which a panic must not unwind through.
+/// Panics are caught instead and logged, and the call or the load which panicked fails.
+#[derive(Debug, Clone)]
+pub enum HandlerPanic {
+  /// The RPC handler, an interceptor or the callback of a [`Dispatcher::call_js`] call panicked.
+  /// A request is answered with an [`RpcError::INTERNAL_ERROR`].
+  Rpc {
+    /// The method of the request, `None` for the reply to a [`Dispatcher::call_js`] call.
+    method: Option<String>,
+    message: String,
+  },
+  /// A custom protocol handler panicked. The request is answered with a `500 Internal Server
+  /// Error` status, as if the handler returned an error, but the panic isn't passed to the
+  /// error handler as well.
+  Protocol { uri: String, message: String },
+  /// The request interceptor panicked. The request is allowed.
+  Interceptor { uri: String, message: String },
+  /// The error handler panicked on the given error.
+  ErrorHandler { error: String, message: String },
+}
+
+impl fmt::Display for HandlerPanic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      HandlerPanic::Rpc {
+        method: Some(method),
+        message,
+      } => write!(f, "RPC handler panicked on {}: {}", method, message),
+      HandlerPanic::Rpc {
+        method: None,
+        message,
+      } => write!(f, "RPC callback panicked: {}", message),
+      HandlerPanic::Protocol { uri, message } => {
+        write!(
+          f,
+          "Custom protocol handler panicked on {}: {}",
+          uri, message
+        )
+      }
+      HandlerPanic::Interceptor { uri, message } => {
+        write!(f, "Request interceptor panicked on {}: {}", uri, message)
+      }
+      HandlerPanic::ErrorHandler { error, message } => {
+        write!(f, "Error handler panicked on {}: {}", error, message)
+      }
+    }
+  }
+}
+
+pub(crate) type PanicHook = Rc<dyn Fn(&HandlerPanic)>;
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+  if let Some(message) = payload.downcast_ref::<&str>() {
+    message.to_string()
+  } else if let Some(message) = payload.downcast_ref::<String>() {
+    message.clone()
   } else {
-    Ok(None)
+    "Box<dyn Any>".into()
+  }
+}
+
+fn report_panic(hook: Option<&PanicHook>, panic: &HandlerPanic) {
+  log::error!("{}", panic);
+  if let Some(hook) = hook {
+    hook(panic);
   }
 }
 
+pub(crate) type ErrorHandler = Rc<dyn Fn(&Window, Error)>;
+
+// Pass an error which happened in a callback of the web engine, and so can't be returned to the
+// application, to the handler set with `WebViewBuilder::with_error_handler`, or log it.
+fn report_error(handler: Option<&ErrorHandler>, window: &Window, error: Error) {
+  match handler {
+    Some(handler) => handler(window, error),
+    None => log::error!("{}", error),
+  }
+}
+
+// WebView2 doesn't support non-standard protocols yet, so the pages of a custom protocol are
+// loaded from `https://custom-protocol-<name>` instead. Get the rest of the URI after this prefix,
+// if it's the one of the protocol and not of another protocol starting with the same name.
+// See https://github.com/MicrosoftEdge/WebView2Feedback/issues/73
+#[cfg(target_os = "windows")]
+fn strip_protocol_prefix<'a>(uri: &'a str, name: &str) -> Option<&'a str> {
+  let rest = uri
+    .strip_prefix("https://custom-protocol-")?
+    .strip_prefix(name)?;
+  if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') {
+    Some(rest)
+  } else {
+    None
+  }
+}
+
+// Undo the protocol workaround in the URI of a page of a custom protocol, like the source of a
+// message or the `Origin` of a request, so it's the one of the custom protocol.
+#[cfg(target_os = "windows")]
+fn unmap_protocol_uri(uri: String, names: &std::collections::HashSet<String>) -> String {
+  names
+    .iter()
+    .find_map(|name| strip_protocol_prefix(&uri, name).map(|rest| format!("{}://{}", name, rest)))
+    .unwrap_or(uri)
+}
+
 /// Builder type of [`WebView`].
 ///
 /// [`WebViewBuilder`] / [`WebView`] are the basic building blocks to constrcut WebView contents and
@@ -85,11 +376,24 @@ pub struct WebViewBuilder {
   transparent: bool,
   tx: Sender<String>,
   rx: Receiver<String>,
+  js_calls: JsCalls,
+  bytes: RpcBytesStore,
   initialization_scripts: Vec<String>,
   window: Window,
   url: Option<Url>,
-  custom_protocols: Vec<(String, Box<dyn Fn(&Window, &str) -> Result<Vec<u8>>>)>,
+  custom_protocols: Vec<(
+    String,
+    ProtocolOptions,
+    Box<dyn Fn(&Window, &Request) -> Result<Response>>,
+  )>,
   rpc_handler: Option<Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>>,
+  rpc_interceptors: Vec<RpcInterceptor>,
+  request_interceptor: Option<Box<dyn Fn(&Window, &Request) -> Interception>>,
+  panic_hook: Option<PanicHook>,
+  error_handler: Option<ErrorHandler>,
+  rpc_timeout: Option<Duration>,
+  rpc_codec: Option<Arc<dyn RpcCodec>>,
+  rpc_acl: RpcAcl,
   file_drop_handler: Option<Box<dyn Fn(&Window, FileDropEvent) -> bool>>,
   data_directory: Option<PathBuf>,
 }
@@ -102,6 +406,8 @@ impl WebViewBuilder {
     Ok(Self {
       tx,
       rx,
+      js_calls: JsCalls::default(),
+      bytes: RpcBytesStore::default(),
       initialization_scripts: vec![r#"
         document.addEventListener('mousedown', (e) => {
           if (e.target.classList.contains('drag-region') && e.buttons === 1) {
@@ -115,6 +421,13 @@ impl WebViewBuilder {
       transparent: false,
       custom_protocols: vec![],
       rpc_handler: None,
+      rpc_interceptors: vec![],
+      request_interceptor: None,
+      panic_hook: None,
+      error_handler: None,
+      rpc_timeout: None,
+      rpc_codec: None,
+      rpc_acl: RpcAcl::default(),
       file_drop_handler: None,
       data_directory: None,
     })
@@ -146,16 +459,96 @@ impl WebViewBuilder {
   /// safe because it must be run on the main thread who creates it. [`Dispatcher`] can let you
   /// send the scripts from other threads.
   pub fn dispatcher(&self) -> Dispatcher {
-    Dispatcher(self.tx.clone())
+    Dispatcher {
+      tx: self.tx.clone(),
+      js_calls: self.js_calls.clone(),
+      bytes: self.bytes.clone(),
+      codec: self.rpc_codec.clone(),
+    }
   }
 
   /// Register custom file loading protocol
+  ///
+  /// The `wry-rpc` and `wry-intercept` names are reserved by the RPC client and the request
+  /// interceptor, [`WebViewBuilder::build`] fails with [`Error::ReservedProtocol`] if they're used.
+  ///
+  /// The handler receives the [`Request`] made by the page, with its method, URI, headers and
+  /// body, and answers it with a [`Response`] giving the status, the headers and the content
+  /// type, which is guessed from the body and the URI when it's not set. Large files or
+  /// generated content can be streamed with [`Response::from_reader`], or with
+  /// [`Response::from_seekable`] to answer the `Range` requests of the media elements. An error is
+  /// answered with a `500 Internal Server Error` and passed to the handler set with
+  /// [`WebViewBuilder::with_error_handler`].
+  ///
+  /// ```no_run
+  /// # fn main() -> wry::Result<()> {
+  /// use wry::{
+  ///   application::{event_loop::EventLoop, window::WindowBuilder},
+  ///   http::{Method, Response, StatusCode},
+  ///   webview::WebViewBuilder,
+  /// };
+  ///
+  /// let event_loop = EventLoop::new();
+  /// let window = WindowBuilder::new().build(&event_loop)?;
+  /// let _webview = WebViewBuilder::new(window)?
+  ///   .with_custom_protocol("app".into(), |_, request| {
+  ///     Ok(match *request.method() {
+  ///       // Echo the body of `fetch("app://echo", { method: "POST", body })`
+  ///       Method::POST => Response::new(request.body().to_vec()),
+  ///       Method::GET => Response::new(b"<h1>Hello</h1>".to_vec()),
+  ///       _ => Response::new(vec![]).with_status(StatusCode::METHOD_NOT_ALLOWED),
+  ///     })
+  ///   })
+  ///   .with_url("app://index.html")?
+  ///   .build()?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "protocol")]
+  pub fn with_custom_protocol<F>(self, name: String, handler: F) -> Self
+  where
+    F: Fn(&Window, &Request) -> Result<Response> + 'static,
+  {
+    self.with_custom_protocol_options(name, ProtocolOptions::new(), handler)
+  }
+
+  /// Register custom file loading protocol like [`WebViewBuilder::with_custom_protocol`], with
+  /// the [`ProtocolOptions`] telling how the pages treat it.
+  ///
+  /// ```no_run
+  /// # fn main() -> wry::Result<()> {
+  /// use wry::{
+  ///   application::{event_loop::EventLoop, window::WindowBuilder},
+  ///   http::Response,
+  ///   webview::{ProtocolOptions, WebViewBuilder},
+  /// };
+  ///
+  /// let event_loop = EventLoop::new();
+  /// let window = WindowBuilder::new().build(&event_loop)?;
+  /// // Let `https://example.com` fetch `app://data.json`
+  /// let options = ProtocolOptions::new().with_cors_enabled(true);
+  /// let _webview = WebViewBuilder::new(window)?
+  ///   .with_custom_protocol_options("app".into(), options, |_, _| {
+  ///     Ok(Response::new(b"{}".to_vec()))
+  ///   })
+  ///   .with_url("https://example.com")?
+  ///   .build()?;
+  /// # Ok(())
+  /// # }
+  /// ```
   #[cfg(feature = "protocol")]
-  pub fn with_custom_protocol<F>(mut self, name: String, handler: F) -> Self
+  pub fn with_custom_protocol_options<F>(
+    mut self,
+    name: String,
+    options: ProtocolOptions,
+    handler: F,
+  ) -> Self
   where
-    F: Fn(&Window, &str) -> Result<Vec<u8>> + 'static,
+    F: Fn(&Window, &Request) -> Result<Response> + 'static,
   {
-    self.custom_protocols.push((name, Box::new(handler)));
+    self
+      .custom_protocols
+      .push((name, options, Box::new(handler)));
     self
   }
 
@@ -168,66 +561,203 @@ impl WebViewBuilder {
   /// 1. The `call()` function accepts a method name and parameters and expects a reply.
   /// 2. The `notify()` function accepts a method name and parameters but does not expect a reply.
   ///
-  /// Both functions return promises but `notify()` resolves immediately.
+  /// Both functions return promises but `notify()` resolves immediately. Several calls and
+  /// notifications can also be sent at once with `batch()`, which takes an array of
+  /// `{method, params, notify}` objects and returns a promise of the settled result of each of
+  /// them, like `Promise.allSettled` does. A call answered with an
+  /// error is rejected with an instance of `window.rpc.RpcError`, which carries the `code`,
+  /// `message` and `data` of the [`RpcError`] so the page can branch on the code. Binary data can
+  /// be passed as `ArrayBuffer` or typed arrays and returned as `Uint8Array` with [`RpcBytes`].
+  ///
+  /// The other way around, functions registered with `window.rpc.register(name, function)` can be
+  /// called from Rust with [`WebView::call_js`] or [`Dispatcher::call_js`], and listeners added with
+  /// `window.rpc.on(event, listener)` receive the events sent with [`WebView::emit`] or
+  /// [`Dispatcher::emit`]. `window.rpc.on` returns a function removing the listener, which can
+  /// also be done with `window.rpc.off(event, listener)`.
+  ///
+  /// The handler runs on the main thread. Returning a [`RpcResponse`] replies to the call right
+  /// away. To reply later instead, for example after doing some work on another thread, take the
+  /// [`RpcResponder`] of the request with [`RpcRequest::responder`] and return `None`. Calls made
+  /// with `window.rpc.stream(method, ...params)` get many results through an [`RpcSink`] instead,
+  /// which the page iterates with `for await`. A call can be cancelled by passing an
+  /// `AbortSignal` as its last argument, which the handler sees through the
+  /// [`CancellationToken`] of the request.
   pub fn with_rpc_handler<F>(mut self, handler: F) -> Self
   where
     F: Fn(&Window, RpcRequest) -> Option<RpcResponse> + 'static,
   {
-    let js = r#"
-            (function() {
-                function Rpc() {
-                    const self = this;
-                    this._promises = {};
-
-                    // Private internal function called on error
-                    this._error = (id, error) => {
-                        if(this._promises[id]){
-                            this._promises[id].reject(error);
-                            delete this._promises[id];
-                        }
-                    }
-
-                    // Private internal function called on result
-                    this._result = (id, result) => {
-                        if(this._promises[id]){
-                            this._promises[id].resolve(result);
-                            delete this._promises[id];
-                        }
-                    }
-
-                    // Call remote method and expect a reply from the handler
-                    this.call = function(method) {
-                        let array = new Uint32Array(1);
-                        window.crypto.getRandomValues(array);
-                        const id = array[0];
-                        const params = Array.prototype.slice.call(arguments, 1);
-                        const payload = {jsonrpc: "2.0", id, method, params};
-                        const promise = new Promise((resolve, reject) => {
-                            self._promises[id] = {resolve, reject};
-                        });
-                        window.external.invoke(JSON.stringify(payload));
-                        return promise;
-                    }
-
-                    // Send a notification without an `id` so no reply is expected.
-                    this.notify = function(method) {
-                        const params = Array.prototype.slice.call(arguments, 1);
-                        const payload = {jsonrpc: "2.0", method, params};
-                        window.external.invoke(JSON.stringify(payload));
-                        return Promise.resolve();
-                    }
-                }
-                window.external = window.external || {};
-                window.external.rpc = new Rpc();
-                window.rpc = window.external.rpc;
-            })();
-            "#;
-
-    self.initialization_scripts.push(js.to_string());
     self.rpc_handler = Some(Box::new(handler));
     self
   }
 
+  /// Set the default timeout of the calls made with `window.rpc.call`. A call which isn't answered
+  /// in time is rejected with a `window.rpc.RpcError` of code [`RpcError::TIMEOUT`], so promises
+  /// of calls the handler never replies to don't pile up. There is no timeout by default.
+  ///
+  /// The timeout can also be changed on the Javascript side by setting `window.rpc.timeout` in
+  /// milliseconds, or for a single call with
+  /// `window.rpc.callWithOptions(method, params, { timeout })`.
+  ///
+  /// The same timeout applies to the calls made from Rust with [`Dispatcher::call_js`].
+  pub fn with_rpc_timeout(mut self, timeout: Duration) -> Self {
+    self.rpc_timeout = Some(timeout);
+    self.js_calls.set_timeout(Some(timeout));
+    self
+  }
+
+  /// Set the serialization format of the RPC messages exchanged with the page, JSON by default.
+  /// See [`RpcCodec`] for the details.
+  ///
+  /// This should be set before creating any [`Dispatcher`] with [`WebViewBuilder::dispatcher`],
+  /// since the dispatchers keep the codec they were created with.
+  pub fn with_rpc_codec<C: RpcCodec + 'static>(mut self, codec: C) -> Self {
+    self.rpc_codec = Some(Arc::new(codec));
+    self
+  }
+
+  /// Allow the pages of an origin to call the given RPC methods.
+  ///
+  /// By default, any page loaded in the webview can call every method of the RPC handler,
+  /// including remote pages it navigates to. Once an origin is allowed with this method, requests
+  /// from other origins or to other methods are rejected with an [`RpcError::PERMISSION_DENIED`]
+  /// error without reaching the handler. The origin is either a full origin like
+  /// `https://example.com`, or a scheme like `app://` to allow every page of that scheme. A `"*"`
+  /// method allows all of them. Calling this again with the same origin adds to its methods.
+  ///
+  /// The origin of the page which sent a request is available with [`RpcRequest::origin`]. The
+  /// pages of a custom protocol loaded without a host, like `app:///index.html`, have the
+  /// `app://localhost` origin. The replies to [`Dispatcher::call_js`] and the cancellations of
+  /// calls are only accepted from the allowed origins.
+  ///
+  /// Platform-specific behavior:
+  ///
+  /// - **Linux:** Only the top frame of the page can send RPC messages. The messages of the other
+  /// frames and of the previous pages are rejected.
+  pub fn with_rpc_allowlist<I, S>(mut self, origin: &str, methods: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self
+      .rpc_acl
+      .allow(origin, methods.into_iter().map(Into::into));
+    self
+  }
+
+  /// Add an interceptor running around the RPC handler for every request, to implement logging,
+  /// authentication or metrics once for all the methods.
+  ///
+  /// The interceptor receives each request along with an [`RpcNext`] running the rest of the
+  /// chain. It can inspect or change the request before passing it on with [`RpcNext::run`], then
+  /// inspect or change the response it gets back. It can also reply right away without calling the
+  /// handler at all. Interceptors run in the order they are added, the first one being the
+  /// outermost. Replies sent later through an [`RpcResponder`] or an [`RpcSink`] don't go back
+  /// through the interceptors.
+  ///
+  /// ```no_run
+  /// # use wry::{application::{event_loop::EventLoop, window::Window}, webview::WebViewBuilder};
+  /// # let event_loop = EventLoop::new();
+  /// # let window = Window::new(&event_loop).unwrap();
+  /// use std::time::Instant;
+  ///
+  /// let builder = WebViewBuilder::new(window)
+  ///   .unwrap()
+  ///   .with_rpc_interceptor(|window, req, next| {
+  ///     let method = req.method.clone();
+  ///     let start = Instant::now();
+  ///     let response = next.run(window, req);
+  ///     println!("{} took {:?}", method, start.elapsed());
+  ///     response
+  ///   });
+  /// ```
+  pub fn with_rpc_interceptor<F>(mut self, interceptor: F) -> Self
+  where
+    F: Fn(&Window, RpcRequest, RpcNext) -> Option<RpcResponse> + 'static,
+  {
+    self.rpc_interceptors.push(Box::new(interceptor));
+    self
+  }
+
+  /// Set an [`RpcRouter`] as the RPC handler. This is a shorthand of
+  /// [`WebViewBuilder::with_rpc_handler`] which dispatches every request to the method registered
+  /// on the router under the requested name.
+  pub fn with_rpc_router(self, router: RpcRouter) -> Self {
+    self.with_rpc_handler(move |window, req| router.handle(window, req))
+  }
+
+  /// Set an interceptor of the `http` and `https` requests made by the pages, which can let them
+  /// load, block them, redirect them or answer them with a [`Response`], to block trackers, mock
+  /// an API in tests or serve offline fallbacks for example. See [`Interception`].
+  ///
+  /// ```no_run
+  /// # fn main() -> wry::Result<()> {
+  /// use wry::{
+  ///   application::{event_loop::EventLoop, window::WindowBuilder},
+  ///   http::Response,
+  ///   webview::{Interception, WebViewBuilder},
+  /// };
+  ///
+  /// let event_loop = EventLoop::new();
+  /// let window = WindowBuilder::new().build(&event_loop)?;
+  /// let _webview = WebViewBuilder::new(window)?
+  ///   .with_request_interceptor(|_, request| {
+  ///     if request.uri().starts_with("https://tracker.example.com/") {
+  ///       Interception::Block
+  ///     } else if request.uri() == "https://api.example.com/user" {
+  ///       Interception::Respond(Response::new(b"{\"name\":\"wry\"}".to_vec()))
+  ///     } else {
+  ///       Interception::Allow
+  ///     }
+  ///   })
+  ///   .with_url("https://example.com")?
+  ///   .build()?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  ///
+  /// Platform-specific behavior:
+  ///
+  /// - **Linux:** The requests are sent by the web process of WebKitGTK, where they can only be
+  /// changed by the `wry-webextension` library: build it and install `libwry_webextension.so`
+  /// next to the executable. The interceptor doesn't see the request bodies, and the responses are
+  /// loaded from the `wry-intercept` protocol. Without the library, the interceptor is only called
+  /// once the requests are sent, with their URI, and can only block or redirect the page loads.
+  /// - **macOS:** Unsupported, the interceptor is never called.
+  /// - **Windows:** The blocked requests are answered with a `403 Forbidden` status.
+  pub fn with_request_interceptor<F>(mut self, interceptor: F) -> Self
+  where
+    F: Fn(&Window, &Request) -> Interception + 'static,
+  {
+    self.request_interceptor = Some(Box::new(interceptor));
+    self
+  }
+
+  /// Set a hook called with the panics caught in the RPC handler, the RPC interceptors, the
+  /// callbacks of [`Dispatcher::call_js`], the custom protocol handlers, the request interceptor
+  /// and the error handler. See [`HandlerPanic`] for the details. Panics are logged with the `log`
+  /// crate whether a hook is set or not.
+  ///
+  /// This has no effect if the application is built with `panic = "abort"`.
+  pub fn with_panic_hook<F>(mut self, hook: F) -> Self
+  where
+    F: Fn(&HandlerPanic) + 'static,
+  {
+    self.panic_hook = Some(Rc::new(hook));
+    self
+  }
+
+  /// Set a handler called with the errors which happen while the web engine runs and so can't be
+  /// returned to the application: the RPC messages which can't be parsed, the custom protocol
+  /// handlers which fail, the scripts which fail to evaluate and the pages which fail to load.
+  /// Without a handler, these errors are logged with the `log` crate.
+  pub fn with_error_handler<F>(mut self, handler: F) -> Self
+  where
+    F: Fn(&Window, Error) + 'static,
+  {
+    self.error_handler = Some(Rc::new(handler));
+    self
+  }
+
   /// Set a handler closure to process incoming [`FileDropEvent`] of the webview.
   ///
   /// # Blocking OS Default Behavior
@@ -260,22 +790,146 @@ impl WebViewBuilder {
   ///
   /// [`EventLoop`]: crate::application::event_loop::EventLoop
   pub fn build(self) -> Result<WebView> {
-    let window = Rc::new(self.window);
+    if let Some((name, ..)) = self
+      .custom_protocols
+      .iter()
+      .find(|(name, ..)| name == rpc::RPC_PROTOCOL || name == INTERCEPT_PROTOCOL)
+    {
+      return Err(Error::ReservedProtocol(name.clone()));
+    }
+    let dispatcher = self.dispatcher();
+    // Take the fields apart, since the closures below would capture the whole builder otherwise
+    let WebViewBuilder {
+      transparent,
+      tx,
+      rx,
+      js_calls,
+      bytes,
+      mut initialization_scripts,
+      window,
+      url,
+      mut custom_protocols,
+      rpc_handler,
+      rpc_interceptors,
+      request_interceptor,
+      panic_hook,
+      error_handler,
+      rpc_timeout,
+      rpc_codec,
+      rpc_acl,
+      file_drop_handler,
+      data_directory,
+    } = self;
+    let window = Rc::new(window);
+    if rpc_handler.is_some() {
+      // Make sure the RPC client is ready for any other script using it.
+      initialization_scripts.insert(0, rpc::client_script(rpc_timeout, rpc_codec.as_deref()));
+      // The pages allowed to make RPC calls exchange the RPC bytes with this protocol, whatever
+      // their origin
+      let bytes = bytes.clone();
+      let acl = rpc_acl.clone();
+      custom_protocols.push((
+        rpc::RPC_PROTOCOL.into(),
+        ProtocolOptions::new().with_cors_enabled(true),
+        Box::new(move |_, request| bytes.respond(request, &acl)),
+      ));
+    } else {
+      // Without the RPC client, nothing can reply to the calls
+      js_calls.close("The webview has no RPC handler", panic_hook.as_ref());
+    }
+    let error_handler = error_handler.map(|handler| {
+      let panic_hook = panic_hook.clone();
+      let handler: ErrorHandler = Rc::new(move |window, error| {
+        let description = error.to_string();
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| handler(window, error))) {
+          let panic = HandlerPanic::ErrorHandler {
+            error: description,
+            message: panic_message(&*payload),
+          };
+          report_panic(panic_hook.as_ref(), &panic);
+        }
+      });
+      handler
+    });
+    let custom_protocols = custom_protocols
+      .into_iter()
+      .map(|(name, options, handler)| {
+        let panic_hook = panic_hook.clone();
+        let error_handler = error_handler.clone();
+        let handler: Box<dyn Fn(&Window, &Request) -> Response> =
+          Box::new(move |window, request| {
+            panic::catch_unwind(AssertUnwindSafe(|| handler(window, request)))
+              .unwrap_or_else(|payload| {
+                let panic = HandlerPanic::Protocol {
+                  uri: request.uri().into(),
+                  message: panic_message(&*payload),
+                };
+                report_panic(panic_hook.as_ref(), &panic);
+                Err(Error::HandlerPanic(panic))
+              })
+              .and_then(|response| response.ranged(request).map_err(Error::from))
+              .unwrap_or_else(|e| {
+                // Answer with an error status rather than failing the load, so a `fetch` gets
+                // a response
+                let response = Response::new(e.to_string().into_bytes())
+                  .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                  .with_mimetype("text/plain");
+                // Panics were reported already
+                if !matches!(e, Error::HandlerPanic(_)) {
+                  report_error(error_handler.as_ref(), window, e);
+                }
+                response
+              })
+          });
+        (name, options, handler)
+      })
+      .collect();
+    let request_interceptor = request_interceptor.map(|interceptor| {
+      let panic_hook = panic_hook.clone();
+      let interceptor: Box<dyn Fn(&Window, &Request) -> Interception> =
+        Box::new(move |window, request| {
+          panic::catch_unwind(AssertUnwindSafe(|| interceptor(window, request))).unwrap_or_else(
+            |payload| {
+              let panic = HandlerPanic::Interceptor {
+                uri: request.uri().into(),
+                message: panic_message(&*payload),
+              };
+              report_panic(panic_hook.as_ref(), &panic);
+              Interception::Allow
+            },
+          )
+        });
+      interceptor
+    });
+    let rpc_handler = rpc_handler.map(|handler| RpcHandler {
+      handler,
+      interceptors: rpc_interceptors,
+      dispatcher,
+      cancellations: Default::default(),
+      acl: rpc_acl,
+      panic_hook,
+      error_handler: error_handler.clone(),
+    });
     let webview = InnerWebView::new(
       window.clone(),
-      self.initialization_scripts,
-      self.url,
-      self.transparent,
-      self.custom_protocols,
-      self.rpc_handler,
-      self.file_drop_handler,
-      self.data_directory,
+      initialization_scripts,
+      url,
+      transparent,
+      custom_protocols,
+      request_interceptor,
+      rpc_handler,
+      file_drop_handler,
+      data_directory,
+      error_handler,
     )?;
     Ok(WebView {
       window,
       webview,
-      tx: self.tx,
-      rx: self.rx,
+      tx,
+      rx,
+      js_calls,
+      bytes,
+      codec: rpc_codec,
     })
   }
 }
@@ -291,6 +945,9 @@ pub struct WebView {
   webview: InnerWebView,
   tx: Sender<String>,
   rx: Receiver<String>,
+  js_calls: JsCalls,
+  bytes: RpcBytesStore,
+  codec: Option<Arc<dyn RpcCodec>>,
 }
 
 impl WebView {
@@ -319,7 +976,43 @@ impl WebView {
   /// safe because it must be run on the main thread who creates it. [`Dispatcher`] can let you
   /// send the scripts from other threads.
   pub fn dispatcher(&self) -> Dispatcher {
-    Dispatcher(self.tx.clone())
+    Dispatcher {
+      tx: self.tx.clone(),
+      js_calls: self.js_calls.clone(),
+      bytes: self.bytes.clone(),
+      codec: self.codec.clone(),
+    }
+  }
+
+  /// Call a Javascript function registered with `window.rpc.register(name, function)` and get its
+  /// result with the callback. See [`Dispatcher::call_js`] for the details.
+  ///
+  /// Unlike [`WebView::dispatch_script`], the call is evaluated right away.
+  pub fn call_js<P, F>(&self, method: &str, params: P, callback: F) -> Result<()>
+  where
+    P: Serialize,
+    F: FnOnce(Result<Value>) + Send + 'static,
+  {
+    match self
+      .js_calls
+      .call(self.codec.as_deref(), &self.bytes, method, params, callback)?
+    {
+      Some(js) => self.webview.eval(&js),
+      None => Ok(()),
+    }
+  }
+
+  /// Emit an event to the listeners registered with `window.rpc.on(event, listener)`. See
+  /// [`Dispatcher::emit`] for the details.
+  ///
+  /// Unlike [`WebView::dispatch_script`], the event is emitted right away.
+  pub fn emit<T: Serialize>(&self, event: &str, payload: T) -> Result<()> {
+    self.webview.eval(&rpc::emit_script(
+      self.codec.as_deref(),
+      &self.bytes,
+      event,
+      payload,
+    )?)
   }
 
   /// Get the [`Window`] associate with the [`WebView`]. This can let you perform window related
@@ -350,84 +1043,83 @@ impl WebView {
   }
 }
 
+impl Drop for WebView {
+  fn drop(&mut self) {
+    self.js_calls.close("The webview was dropped", None);
+    self.bytes.clear();
+  }
+}
+
 #[derive(Clone)]
 /// A channel sender to dispatch javascript code to for the [`WebView`] to evaluate it.
 ///
 /// [`WebView`] is not thread safe because it must be run on main thread who creates it.
 /// [`Dispatcher`] can let you send scripts from other thread.
-pub struct Dispatcher(Sender<String>);
+pub struct Dispatcher {
+  tx: Sender<String>,
+  js_calls: JsCalls,
+  bytes: RpcBytesStore,
+  codec: Option<Arc<dyn RpcCodec>>,
+}
 
 impl Dispatcher {
   /// Dispatch javascript code to be evaluated later. Note this will not actually run the
   /// scripts being dispatched. Users need to call [`WebView::evaluate_script`] to execute them.
   pub fn dispatch_script(&self, js: &str) -> Result<()> {
-    self.0.send(js.to_string())?;
+    self.tx.send(js.to_string())?;
     Ok(())
   }
-}
-
-const RPC_VERSION: &str = "2.0";
-
-/// RPC request message.
-///
-/// This usually passes to the [`RpcHandler`] or [`WindowRpcHandler`](crate::WindowRpcHandler) as
-/// the parameter. You don't create this by yourself.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RpcRequest {
-  jsonrpc: String,
-  pub id: Option<Value>,
-  pub method: String,
-  pub params: Option<Value>,
-}
-
-/// RPC response message which being sent back to the Javascript side.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RpcResponse {
-  jsonrpc: String,
-  pub(crate) id: Option<Value>,
-  pub(crate) result: Option<Value>,
-  pub(crate) error: Option<Value>,
-}
-
-impl RpcResponse {
-  /// Create a new result response.
-  pub fn new_result(id: Option<Value>, result: Option<Value>) -> Self {
-    Self {
-      jsonrpc: RPC_VERSION.to_string(),
-      id,
-      result,
-      error: None,
-    }
-  }
 
-  /// Create a new error response.
-  pub fn new_error(id: Option<Value>, error: Option<Value>) -> Self {
-    Self {
-      jsonrpc: RPC_VERSION.to_string(),
-      id,
-      error,
-      result: None,
+  /// Call a Javascript function registered with `window.rpc.register(name, function)` and get its
+  /// result with the callback.
+  ///
+  /// If `params` serializes to an array, its items are passed as the arguments of the function,
+  /// otherwise the value is passed as the only argument. The callback receives the value returned
+  /// by the function, after waiting for it if it's a promise, or an [`Error::RpcError`] if the
+  /// function throws or isn't registered. The callback runs on the main thread once the reply is
+  /// received.
+  ///
+  /// The callback always runs once. If the page navigates away or the [`WebView`] is dropped
+  /// before replying, it receives an [`RpcError::CANCELLED`] error. If a timeout is set with
+  /// [`WebViewBuilder::with_rpc_timeout`] and the page doesn't reply in time, it receives an
+  /// [`RpcError::TIMEOUT`] error, on another thread.
+  ///
+  /// This relies on the RPC client injected by [`WebViewBuilder::with_rpc_handler`], so a handler
+  /// must be set for the call to be answered, otherwise the callback fails right away. Like
+  /// [`Dispatcher::dispatch_script`], the call is sent once [`WebView::evaluate_script`] runs.
+  pub fn call_js<P, F>(&self, method: &str, params: P, callback: F) -> Result<()>
+  where
+    P: Serialize,
+    F: FnOnce(Result<Value>) + Send + 'static,
+  {
+    match self
+      .js_calls
+      .call(self.codec.as_deref(), &self.bytes, method, params, callback)?
+    {
+      Some(js) => self.dispatch_script(&js),
+      None => Ok(()),
     }
   }
 
-  /// Get a script that resolves the promise with a result.
-  pub fn get_result_script(id: Value, result: Value) -> Result<String> {
-    let retval = serde_json::to_string(&result)?;
-    Ok(format!(
-      "window.external.rpc._result({}, {})",
-      id.to_string(),
-      retval
-    ))
+  /// Emit an event to the listeners registered with `window.rpc.on(event, listener)`.
+  ///
+  /// The payload is serialized with the codec set with [`WebViewBuilder::with_rpc_codec`], JSON
+  /// by default, and every listener of the event is called with it as the only argument. This
+  /// relies on the RPC client injected by [`WebViewBuilder::with_rpc_handler`], so a handler must
+  /// be set for the event to be received.
+  pub fn emit<T: Serialize>(&self, event: &str, payload: T) -> Result<()> {
+    self.dispatch_script(&rpc::emit_script(
+      self.codec.as_deref(),
+      &self.bytes,
+      event,
+      payload,
+    )?)
   }
 
-  /// Get a script that rejects the promise with an error.
-  pub fn get_error_script(id: Value, result: Value) -> Result<String> {
-    let retval = serde_json::to_string(&result)?;
-    Ok(format!(
-      "window.external.rpc._error({}, {})",
-      id.to_string(),
-      retval
-    ))
+  // Fail the calls and drop the bytes of the previous page, once a new one is loaded.
+  pub(crate) fn navigated(&self, panic_hook: Option<&PanicHook>) {
+    self.js_calls.navigated(panic_hook);
+    self.bytes.clear();
   }
 }
 
@@ -441,3 +1133,83 @@ pub enum FileDropEvent {
   /// The file drop was aborted.
   Cancelled,
 }
+
+/// How the pages treat a custom protocol registered with
+/// [`WebViewBuilder::with_custom_protocol_options`], to choose the isolation it needs.
+///
+/// The protocols are secure and nothing else by default.
+///
+/// Platform-specific behavior:
+///
+/// - **macOS / Windows:** Unsupported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolOptions {
+  pub(crate) secure: bool,
+  pub(crate) cors_enabled: bool,
+  pub(crate) local: bool,
+  pub(crate) display_isolated: bool,
+  pub(crate) empty_document: bool,
+}
+
+impl ProtocolOptions {
+  /// Create the default options of a secure protocol.
+  pub fn new() -> Self {
+    Self {
+      secure: true,
+      cors_enabled: false,
+      local: false,
+      display_isolated: false,
+      empty_document: false,
+    }
+  }
+
+  /// Whether the protocol is secure like `https`, so its resources aren't blocked as mixed content
+  /// by the secure pages.
+  pub fn with_secure(mut self, secure: bool) -> Self {
+    self.secure = secure;
+    self
+  }
+
+  /// Whether the protocol can be fetched from other origins with the CORS checks, like from an
+  /// `https` page.
+  pub fn with_cors_enabled(mut self, cors_enabled: bool) -> Self {
+    self.cors_enabled = cors_enabled;
+    self
+  }
+
+  /// Whether the protocol is local like `file`, so only the local pages can load its resources.
+  pub fn with_local(mut self, local: bool) -> Self {
+    self.local = local;
+    self
+  }
+
+  /// Whether only the pages of the same protocol can display its resources.
+  pub fn with_display_isolated(mut self, display_isolated: bool) -> Self {
+    self.display_isolated = display_isolated;
+    self
+  }
+
+  /// Whether the pages of the protocol are loaded synchronously as empty documents, like
+  /// `about:blank`.
+  pub fn with_empty_document(mut self, empty_document: bool) -> Self {
+    self.empty_document = empty_document;
+    self
+  }
+}
+
+// The protocol serving the responses of the request interceptor on Linux.
+pub(crate) const INTERCEPT_PROTOCOL: &str = "wry-intercept";
+
+/// What to do with a request seen by the interceptor set with
+/// [`WebViewBuilder::with_request_interceptor`].
+#[derive(Debug)]
+pub enum Interception {
+  /// Let the request load.
+  Allow,
+  /// Block the request, which fails to load.
+  Block,
+  /// Load another URL instead.
+  Redirect(String),
+  /// Answer the request with a response instead of loading it.
+  Respond(Response),
+}
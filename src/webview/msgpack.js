@@ -0,0 +1,204 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+// The Javascript counterpart of `MessagePackCodec`, packing the messages exchanged with Rust.
+(function () {
+  // Minimal MessagePack encoder, for the values which can be sent as JSON.
+  function pack(value) {
+    const bytes = [];
+    const utf8 = new TextEncoder();
+    const uint = (n, size) => {
+      for (let i = size - 1; i >= 0; i--) {
+        bytes.push(Math.floor(n / Math.pow(2, i * 8)) & 0xff);
+      }
+    };
+    const header = (length, fix, fixMax, codes) => {
+      if (length <= fixMax) {
+        bytes.push(fix | length);
+      } else if (length < 0x100 && codes[0]) {
+        bytes.push(codes[0], length);
+      } else if (length < 0x10000) {
+        bytes.push(codes[1]);
+        uint(length, 2);
+      } else {
+        bytes.push(codes[2]);
+        uint(length, 4);
+      }
+    };
+    const write = (value) => {
+      if (value && typeof value.toJSON === "function") {
+        value = value.toJSON();
+      }
+      if (value === null || value === undefined || typeof value === "function") {
+        bytes.push(0xc0);
+      } else if (typeof value === "boolean") {
+        bytes.push(value ? 0xc3 : 0xc2);
+      } else if (typeof value === "number") {
+        if (!Number.isSafeInteger(value)) {
+          const view = new DataView(new ArrayBuffer(8));
+          view.setFloat64(0, value);
+          bytes.push(0xcb, ...new Uint8Array(view.buffer));
+        } else if (value >= 0) {
+          if (value < 0x80) {
+            bytes.push(value);
+          } else if (value < 0x100) {
+            bytes.push(0xcc, value);
+          } else if (value < 0x10000) {
+            bytes.push(0xcd);
+            uint(value, 2);
+          } else if (value < 0x100000000) {
+            bytes.push(0xce);
+            uint(value, 4);
+          } else {
+            bytes.push(0xcf);
+            uint(value, 8);
+          }
+        } else if (value >= -0x20) {
+          bytes.push(value & 0xff);
+        } else if (value >= -0x80) {
+          bytes.push(0xd0, value & 0xff);
+        } else if (value >= -0x8000) {
+          bytes.push(0xd1);
+          uint(value & 0xffff, 2);
+        } else if (value >= -0x80000000) {
+          bytes.push(0xd2);
+          uint(value >>> 0, 4);
+        } else {
+          // Two's complement on 64 bits, split in two halves since bitwise operators stop at 32
+          const high = Math.floor(value / 0x100000000);
+          bytes.push(0xd3);
+          uint(high >>> 0, 4);
+          uint(value - high * 0x100000000, 4);
+        }
+      } else if (typeof value === "string") {
+        const encoded = utf8.encode(value);
+        header(encoded.length, 0xa0, 31, [0xd9, 0xda, 0xdb]);
+        for (const byte of encoded) bytes.push(byte);
+      } else if (Array.isArray(value)) {
+        header(value.length, 0x90, 15, [null, 0xdc, 0xdd]);
+        value.forEach(write);
+      } else {
+        const keys = Object.keys(value).filter(
+          (key) => value[key] !== undefined && typeof value[key] !== "function"
+        );
+        header(keys.length, 0x80, 15, [null, 0xde, 0xdf]);
+        for (const key of keys) {
+          write(key);
+          write(value[key]);
+        }
+      }
+    };
+    write(value);
+    return new Uint8Array(bytes);
+  }
+
+  // Minimal MessagePack decoder, for the values sent by Rust.
+  function unpack(bytes) {
+    const view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+    const utf8 = new TextDecoder();
+    let offset = 0;
+    const uint = (size) => {
+      let n = 0;
+      for (let i = 0; i < size; i++) {
+        n = n * 256 + bytes[offset++];
+      }
+      return n;
+    };
+    const int = (size) => {
+      if (size === 8) {
+        // Keep the precision of the high half, which holds the sign
+        const high = int(4);
+        return high * 0x100000000 + uint(4);
+      }
+      const n = uint(size);
+      return n >= Math.pow(2, size * 8 - 1) ? n - Math.pow(2, size * 8) : n;
+    };
+    const str = (length) => {
+      const value = utf8.decode(bytes.subarray(offset, offset + length));
+      offset += length;
+      return value;
+    };
+    const bin = (length) => {
+      const value = bytes.slice(offset, offset + length);
+      offset += length;
+      return value;
+    };
+    const array = (length) => {
+      const value = [];
+      for (let i = 0; i < length; i++) value.push(read());
+      return value;
+    };
+    const map = (length) => {
+      const value = {};
+      for (let i = 0; i < length; i++) {
+        const key = read();
+        value[key] = read();
+      }
+      return value;
+    };
+    const read = () => {
+      const type = bytes[offset++];
+      if (type < 0x80) return type;
+      if (type < 0x90) return map(type & 0x0f);
+      if (type < 0xa0) return array(type & 0x0f);
+      if (type < 0xc0) return str(type & 0x1f);
+      if (type >= 0xe0) return type - 0x100;
+      switch (type) {
+        case 0xc0:
+          return null;
+        case 0xc2:
+          return false;
+        case 0xc3:
+          return true;
+        case 0xc4:
+          return bin(uint(1));
+        case 0xc5:
+          return bin(uint(2));
+        case 0xc6:
+          return bin(uint(4));
+        case 0xca:
+          offset += 4;
+          return view.getFloat32(offset - 4);
+        case 0xcb:
+          offset += 8;
+          return view.getFloat64(offset - 8);
+        case 0xcc:
+          return uint(1);
+        case 0xcd:
+          return uint(2);
+        case 0xce:
+          return uint(4);
+        case 0xcf:
+          return uint(8);
+        case 0xd0:
+          return int(1);
+        case 0xd1:
+          return int(2);
+        case 0xd2:
+          return int(4);
+        case 0xd3:
+          return int(8);
+        case 0xd9:
+          return str(uint(1));
+        case 0xda:
+          return str(uint(2));
+        case 0xdb:
+          return str(uint(4));
+        case 0xdc:
+          return array(uint(2));
+        case 0xdd:
+          return array(uint(4));
+        case 0xde:
+          return map(uint(2));
+        case 0xdf:
+          return map(uint(4));
+        default:
+          throw new Error(`Unsupported MessagePack type: 0x${type.toString(16)}`);
+      }
+    };
+    return read();
+  }
+
+  return { encode: pack, decode: unpack };
+})()
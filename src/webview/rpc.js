@@ -0,0 +1,459 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+(function () {
+  // Error a call is rejected with, carrying the JSON-RPC error object sent by the handler.
+  class RpcError extends Error {
+    constructor(code, message, data) {
+      super(message);
+      this.name = "RpcError";
+      this.code = code;
+      this.data = data;
+    }
+  }
+  RpcError.PARSE_ERROR = -32700;
+  RpcError.INVALID_REQUEST = -32600;
+  RpcError.METHOD_NOT_FOUND = -32601;
+  RpcError.INVALID_PARAMS = -32602;
+  RpcError.INTERNAL_ERROR = -32603;
+  RpcError.SERVER_ERROR = -32000;
+  RpcError.TIMEOUT = -32001;
+  RpcError.PERMISSION_DENIED = -32002;
+  RpcError.CANCELLED = -32800;
+
+  // JSON has no binary type, so the `ArrayBuffer`s and typed arrays of the messages are only
+  // referenced by a token: the bytes sent to Rust are uploaded to the RPC protocol first, and the
+  // bytes returned by Rust are fetched from it.
+  function newToken() {
+    return Array.from(window.crypto.getRandomValues(new Uint32Array(4)))
+      .map((n) => n.toString(16).padStart(8, "0"))
+      .join("");
+  }
+
+  // Replace the bytes of a value by tokens, adding the bytes to upload to `uploads`.
+  function encodeBytes(value, uploads) {
+    if (value instanceof ArrayBuffer || ArrayBuffer.isView(value)) {
+      const bytes =
+        value instanceof ArrayBuffer
+          ? new Uint8Array(value)
+          : new Uint8Array(value.buffer, value.byteOffset, value.byteLength);
+      const token = newToken();
+      uploads.push({ token, bytes });
+      return { __wry_bytes_ref__: token };
+    }
+    if (Array.isArray(value)) {
+      return value.map((item) => encodeBytes(item, uploads));
+    }
+    if (value !== null && typeof value === "object" && value.constructor === Object) {
+      const result = {};
+      for (const key of Object.keys(value)) {
+        result[key] = encodeBytes(value[key], uploads);
+      }
+      return result;
+    }
+    return value;
+  }
+
+  // Upload the bytes of the call `id` before sending it. The request has no content type, so it
+  // needs no CORS preflight.
+  function uploadBytes(uploads, id, url) {
+    const query = id === undefined ? "" : `?id=${encodeURIComponent(id)}`;
+    return Promise.all(
+      uploads.map(({ token, bytes }) =>
+        fetch(url + token + query, { method: "POST", body: bytes }).then((response) => {
+          if (!response.ok) {
+            throw new Error(`Failed to upload the bytes: ${response.status}`);
+          }
+        })
+      )
+    );
+  }
+
+  function isBytesRef(value) {
+    return (
+      value !== null &&
+      typeof value === "object" &&
+      typeof value.__wry_bytes_ref__ === "string" &&
+      Object.keys(value).length === 1
+    );
+  }
+
+  function hasBytes(value) {
+    if (value === null || typeof value !== "object") {
+      return false;
+    }
+    return isBytesRef(value) || Object.keys(value).some((key) => hasBytes(value[key]));
+  }
+
+  function decodeBytes(value, url) {
+    if (isBytesRef(value)) {
+      return fetch(url + value.__wry_bytes_ref__)
+        .then((response) => response.arrayBuffer())
+        .then((buffer) => new Uint8Array(buffer));
+    }
+    if (Array.isArray(value)) {
+      return Promise.all(value.map((item) => decodeBytes(item, url)));
+    }
+    if (value !== null && typeof value === "object") {
+      const keys = Object.keys(value);
+      return Promise.all(keys.map((key) => decodeBytes(value[key], url))).then((values) => {
+        const result = {};
+        keys.forEach((key, i) => (result[key] = values[i]));
+        return result;
+      });
+    }
+    return Promise.resolve(value);
+  }
+
+  function Rpc() {
+    const self = this;
+    this._promises = {};
+    // Ids are made of a random prefix for this page and a counter, so they never collide with
+    // the ones of calls made before a reload which Rust may still be answering.
+    const session = Array.from(window.crypto.getRandomValues(new Uint32Array(2)))
+      .map((n) => n.toString(16))
+      .join("");
+    let counter = 0;
+    // Default timeout of the calls in milliseconds, none if 0
+    this.timeout = 0;
+    // Codec packing the messages exchanged with Rust, with `encode` and `decode` functions, or
+    // `null` for JSON
+    this._codec = null;
+    this._functions = {};
+    this._listeners = {};
+    this._events = Promise.resolve();
+    this.RpcError = RpcError;
+
+    // Private internal function called on error
+    this._error = (id, error) => {
+      // The requests which aren't valid enough to tell their id
+      if (id === null) {
+        console.error(new RpcError(error.code, error.message, error.data));
+      }
+      if (this._promises[id]) {
+        clearTimeout(this._promises[id].timer);
+        if (error === null || typeof error !== "object") {
+          error = { code: RpcError.SERVER_ERROR, message: String(error) };
+        }
+        this._promises[id].reject(new RpcError(error.code, error.message, error.data));
+        delete this._promises[id];
+      }
+    };
+
+    // Private internal function called on result
+    this._result = (id, result) => {
+      if (this._promises[id]) {
+        clearTimeout(this._promises[id].timer);
+        this._promises[id].resolve(this._decode(result));
+        delete this._promises[id];
+      }
+    };
+
+    // Private internal function called with the responses of a batch
+    this._batch = (responses) => {
+      for (const response of responses) {
+        if (response.error) {
+          this._error(response.id, response.error);
+        } else {
+          this._result(response.id, response.result);
+        }
+      }
+    };
+
+    // Messages waiting for their bytes to be uploaded, which are sent in order
+    let outbox = Promise.resolve();
+    let queued = 0;
+
+    // Private internal function sending a message to Rust once its bytes are uploaded. With a
+    // codec, the packed message is uploaded as well and only its token is sent.
+    this._send = (message, uploads = []) => {
+      let text;
+      if (this._codec) {
+        const token = newToken();
+        uploads = uploads.concat([{ token, bytes: this._codec.encode(message) }]);
+        text = "packed:" + token;
+      } else {
+        text = JSON.stringify(message);
+      }
+      if (uploads.length === 0 && queued === 0) {
+        window.external.invoke(text);
+        return Promise.resolve();
+      }
+      queued++;
+      const uploaded = uploadBytes(uploads, message.id, this._bytesUrl);
+      const sent = outbox.then(() => uploaded).then(() => window.external.invoke(text));
+      outbox = sent.catch(() => {}).then(() => queued--);
+      return sent;
+    };
+
+    // Private internal function sending calls, rejecting them if their bytes can't be uploaded
+    this._sendCalls = (message, uploads, ids) => {
+      this._send(message, uploads).catch((error) => {
+        for (const id of ids) {
+          this._error(id, { code: RpcError.INTERNAL_ERROR, message: error.message });
+        }
+      });
+    };
+
+    // Messages packed by Rust, which are handled in order once fetched
+    let inbox = Promise.resolve();
+
+    // Private internal function called with the token of a message packed by Rust, made of the
+    // name of the function handling it and its arguments
+    this._receive = (token) => {
+      const message = fetch(this._bytesUrl + token)
+        .then((response) => {
+          if (!response.ok) {
+            throw new Error(`Failed to receive a message: ${response.status}`);
+          }
+          return response.arrayBuffer();
+        })
+        .then((buffer) => this._codec.decode(new Uint8Array(buffer)));
+      inbox = inbox
+        .then(() => message)
+        .then(([name, ...args]) => {
+          if (["_result", "_error", "_batch", "_next", "_invoke", "_emit"].includes(name)) {
+            this[name](...args);
+          }
+        })
+        .catch((error) => console.error(error));
+    };
+
+    // Private internal function called with an item of a stream
+    this._next = (id, item) => {
+      if (this._promises[id] && this._promises[id].next) {
+        this._promises[id].next(this._decode(item));
+      }
+    };
+
+    // Private internal function giving up on a call, telling Rust to stop working on it
+    this._cancel = (id, error = { code: RpcError.CANCELLED, message: "The call was cancelled" }) => {
+      if (this._promises[id]) {
+        this._error(id, error);
+        const payload = { jsonrpc: "2.0", method: "$/cancelRequest", params: { id } };
+        self._send(payload);
+      }
+    };
+
+    // Private internal function turning the bytes references of a value into `Uint8Array`s
+    this._decode = (value) => (hasBytes(value) ? decodeBytes(value, this._bytesUrl) : value);
+
+    // Private internal function creating the id and the pending promise of a call
+    this._request = (method, params, options = {}) => {
+      const id = `${session}-${++counter}`;
+      const uploads = [];
+      const payload = { jsonrpc: "2.0", id, method, params: encodeBytes(params, uploads) };
+      const timeout = options.timeout === undefined ? self.timeout : options.timeout;
+      const promise = new Promise((resolve, reject) => {
+        const timer =
+          timeout > 0
+            ? setTimeout(() => {
+                self._cancel(id, {
+                  code: RpcError.TIMEOUT,
+                  message: `Call to ${method} timed out after ${timeout}ms`,
+                });
+              }, timeout)
+            : undefined;
+        self._promises[id] = { resolve, reject, timer, next: options.next };
+      });
+      if (options.signal) {
+        const abort = () => self._cancel(id);
+        const cleanup = () => options.signal.removeEventListener("abort", abort);
+        options.signal.addEventListener("abort", abort);
+        promise.then(cleanup, cleanup);
+      }
+      return { payload, promise, uploads };
+    };
+
+    // Call remote method and expect a reply from the handler. An `AbortSignal` passed as the last
+    // argument cancels the call once aborted.
+    this.call = function (method) {
+      const params = Array.prototype.slice.call(arguments, 1);
+      const last = params[params.length - 1];
+      if (typeof AbortSignal !== "undefined" && last instanceof AbortSignal) {
+        return self.callWithOptions(method, params.slice(0, -1), { signal: last });
+      }
+      return self.callWithOptions(method, params);
+    };
+
+    // Same as `call` with the parameters as an array and some options:
+    // - `timeout`: milliseconds to wait for the reply, overriding `rpc.timeout`.
+    // - `signal`: an `AbortSignal` cancelling the call once aborted.
+    this.callWithOptions = function (method, params, options = {}) {
+      if (options.signal && options.signal.aborted) {
+        return Promise.reject(new RpcError(RpcError.CANCELLED, "The call was cancelled"));
+      }
+      const { payload, promise, uploads } = self._request(method, params, options);
+      self._sendCalls(payload, uploads, [payload.id]);
+      return promise;
+    };
+
+    // Call remote method and iterate the results it streams with `for await`. Breaking out of the
+    // loop cancels the call.
+    this.stream = function (method) {
+      const params = Array.prototype.slice.call(arguments, 1);
+      const queue = [];
+      let wake = null;
+      const push = (item) => {
+        queue.push(item);
+        if (wake) {
+          wake();
+          wake = null;
+        }
+      };
+      const { payload, promise, uploads } = self._request(method, params, {
+        timeout: 0,
+        next: (value) => push({ value }),
+      });
+      promise.then(
+        () => push({ done: true }),
+        (error) => push({ error })
+      );
+      self._sendCalls(payload, uploads, [payload.id]);
+
+      let finished = false;
+      return {
+        async next() {
+          while (!finished) {
+            if (queue.length === 0) {
+              await new Promise((resolve) => (wake = resolve));
+              continue;
+            }
+            const item = queue.shift();
+            if (item.error) {
+              finished = true;
+              throw item.error;
+            }
+            if (item.done) {
+              break;
+            }
+            return { value: await item.value, done: false };
+          }
+          finished = true;
+          return { value: undefined, done: true };
+        },
+        async return() {
+          if (!finished) {
+            finished = true;
+            self._cancel(payload.id);
+          }
+          return { value: undefined, done: true };
+        },
+        [Symbol.asyncIterator]() {
+          return this;
+        },
+      };
+    };
+
+    // Send several calls and notifications in a single message. Each entry is an object like
+    // `{method: "add", params: [1, 2]}`, with `notify: true` for notifications. The returned
+    // promise resolves once every call is settled, with the same shape as `Promise.allSettled`.
+    this.batch = function (entries) {
+      const payloads = [];
+      const promises = [];
+      const uploads = [];
+      for (const { method, params = [], notify = false } of entries) {
+        if (notify) {
+          payloads.push({ jsonrpc: "2.0", method, params: encodeBytes(params, uploads) });
+          promises.push(Promise.resolve());
+        } else {
+          const request = self._request(method, params);
+          uploads.push(...request.uploads);
+          payloads.push(request.payload);
+          promises.push(request.promise);
+        }
+      }
+      if (payloads.length > 0) {
+        const ids = payloads.map((payload) => payload.id).filter((id) => id !== undefined);
+        self._sendCalls(payloads, uploads, ids);
+      }
+      return Promise.allSettled(promises);
+    };
+
+    // Private internal function called by Rust to invoke a registered function
+    this._invoke = (id, method, params) => {
+      const reply = (message, uploads) => {
+        const payload = Object.assign({ jsonrpc: "2.0", id }, message);
+        self._send(payload, uploads).catch((error) =>
+          reply({ error: { code: RpcError.INTERNAL_ERROR, message: error.message } })
+        );
+      };
+      const fn = this._functions[method];
+      if (!fn) {
+        reply({
+          error: { code: RpcError.METHOD_NOT_FOUND, message: `Method not found: ${method}` },
+        });
+        return;
+      }
+      Promise.resolve(this._decode(params))
+        .then((params) => (Array.isArray(params) ? fn(...params) : fn(params)))
+        .then(
+          (result) => {
+            const uploads = [];
+            reply({ result: result === undefined ? null : encodeBytes(result, uploads) }, uploads);
+          },
+          (error) => {
+            const rpcError = error instanceof RpcError;
+            reply({
+              error: {
+                code: rpcError ? error.code : RpcError.SERVER_ERROR,
+                message: error instanceof Error ? error.message : String(error),
+                data: rpcError ? error.data : undefined,
+              },
+            });
+          }
+        );
+    };
+
+    // Register a function Rust can call with `call_js`. The function may return a promise.
+    this.register = function (name, fn) {
+      self._functions[name] = fn;
+    };
+
+    // Remove a function registered with `register`.
+    this.unregister = function (name) {
+      delete self._functions[name];
+    };
+
+    // Private internal function called by Rust to emit an event
+    this._emit = (event, payload) => {
+      // The events are dispatched in order, once the bytes of each are fetched
+      const decoded = this._decode(payload);
+      this._events = Promise.all([this._events, decoded])
+        .then(([, payload]) => {
+          for (const listener of (this._listeners[event] || []).slice()) {
+            try {
+              listener(payload);
+            } catch (e) {
+              console.error(e);
+            }
+          }
+        })
+        .catch((error) => console.error(error));
+    };
+
+    // Listen to an event emitted by Rust. Returns a function removing the listener.
+    this.on = function (event, listener) {
+      (self._listeners[event] = self._listeners[event] || []).push(listener);
+      return () => self.off(event, listener);
+    };
+
+    // Remove a listener added with `on`, or all the listeners of the event if none is given.
+    this.off = function (event, listener) {
+      const listeners = self._listeners[event] || [];
+      self._listeners[event] = listener ? listeners.filter((l) => l !== listener) : [];
+    };
+
+    // Send a notification without an `id` so no reply is expected.
+    this.notify = function (method) {
+      const params = Array.prototype.slice.call(arguments, 1);
+      const uploads = [];
+      const payload = { jsonrpc: "2.0", method, params: encodeBytes(params, uploads) };
+      return self._send(payload, uploads);
+    };
+  }
+  window.external = window.external || {};
+  window.external.rpc = new Rpc();
+  window.rpc = window.external.rpc;
+})();
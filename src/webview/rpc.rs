@@ -0,0 +1,1802 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+  fmt,
+  marker::PhantomData,
+  mem,
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    Arc, Mutex, MutexGuard, Weak,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use url::Url;
+
+use super::{
+  typescript::{self, TsSignature, TsType},
+  Dispatcher, ErrorHandler, HandlerPanic, PanicHook,
+};
+use crate::{
+  application::window::Window,
+  http::{header, HeaderValue, Method, Request, Response, StatusCode},
+  Error, Result,
+};
+
+const RPC_VERSION: &str = "2.0";
+
+/// Name of the custom protocol the [`RpcBytes`] are exchanged with Javascript through.
+pub(crate) const RPC_PROTOCOL: &str = "wry-rpc";
+
+/// Method of the notification sent by the page when it gives up on a call.
+pub(crate) const CANCEL_METHOD: &str = "$/cancelRequest";
+
+#[cfg(target_os = "windows")]
+const RPC_PROTOCOL_URL: &str = "https://custom-protocol-wry-rpc/";
+#[cfg(not(target_os = "windows"))]
+const RPC_PROTOCOL_URL: &str = "wry-rpc://localhost/";
+
+/// RPC request message.
+///
+/// This usually passes to the [`RpcHandler`] or [`WindowRpcHandler`](crate::WindowRpcHandler) as
+/// the parameter. You don't create this by yourself.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcRequest {
+  jsonrpc: String,
+  pub id: Option<Value>,
+  pub method: String,
+  pub params: Option<Value>,
+  #[serde(skip)]
+  pub(crate) responder: Option<RpcResponder>,
+  #[serde(skip)]
+  pub(crate) origin: Option<String>,
+}
+
+impl RpcRequest {
+  /// Get the origin of the page which sent this request, like `https://example.com` or
+  /// `app://localhost`.
+  ///
+  /// Returns `None` if the page has no origin, such as a `data:` URL.
+  pub fn origin(&self) -> Option<&str> {
+    self.origin.as_deref()
+  }
+
+  /// Get a [`RpcResponder`] to reply to this request later, possibly from another thread.
+  ///
+  /// Returns `None` if the request is a notification, which never gets a reply.
+  pub fn responder(&self) -> Option<RpcResponder> {
+    self.responder.clone()
+  }
+
+  /// Get a [`CancellationToken`] telling whether the page gave up on this request.
+  ///
+  /// Returns `None` if the request is a notification, which can't be cancelled.
+  pub fn cancellation_token(&self) -> Option<CancellationToken> {
+    self
+      .responder
+      .as_ref()
+      .map(|responder| responder.cancelled.clone())
+  }
+
+  /// Get a [`RpcSink`] to send many results to this request, made with `window.rpc.stream`.
+  ///
+  /// Returns `None` if the request is a notification, which never gets a reply.
+  pub fn sink(&self) -> Option<RpcSink> {
+    self.responder.clone().map(RpcSink::new)
+  }
+}
+
+/// RPC response message which being sent back to the Javascript side.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcResponse {
+  jsonrpc: String,
+  pub(crate) id: Option<Value>,
+  pub(crate) result: Option<Value>,
+  pub(crate) error: Option<RpcError>,
+}
+
+impl RpcResponse {
+  /// Create a new result response.
+  pub fn new_result(id: Option<Value>, result: Option<Value>) -> Self {
+    Self {
+      jsonrpc: RPC_VERSION.to_string(),
+      id,
+      result,
+      error: None,
+    }
+  }
+
+  /// Create a new error response.
+  pub fn new_error(id: Option<Value>, error: RpcError) -> Self {
+    Self {
+      jsonrpc: RPC_VERSION.to_string(),
+      id,
+      error: Some(error),
+      result: None,
+    }
+  }
+
+  /// Get a script that settles the promise of the call with this response.
+  pub(crate) fn into_script(
+    mut self,
+    codec: Option<&dyn RpcCodec>,
+    bytes: &RpcBytesStore,
+  ) -> Result<Option<String>> {
+    match self.id.take() {
+      Some(id) => Ok(Some(match self.error.take() {
+        Some(error) => Self::error_script(codec, bytes, &id, &error)?,
+        // No error or result, assume a positive response with empty result (ACK)
+        None => {
+          let result = self.result.take().unwrap_or(Value::Null);
+          bytes.claim(Some(&id), &result);
+          Self::result_script(codec, bytes, &id, result)?
+        }
+      })),
+      None => Ok(None),
+    }
+  }
+
+  /// Get a script that settles the promises of all the calls of a batch at once.
+  pub(crate) fn get_batch_script(
+    codec: Option<&dyn RpcCodec>,
+    bytes: &RpcBytesStore,
+    responses: Vec<RpcResponse>,
+  ) -> Result<String> {
+    for response in &responses {
+      if let Some(result) = &response.result {
+        bytes.claim(response.id.as_ref(), result);
+      }
+    }
+    client_call(
+      codec,
+      bytes,
+      "_batch",
+      vec![serde_json::to_value(responses)?],
+    )
+  }
+
+  /// Get a script that resolves the promise with a result.
+  pub fn get_result_script(id: Value, result: Value) -> Result<String> {
+    Self::result_script(None, &RpcBytesStore::default(), &id, result)
+  }
+
+  /// Get a script that rejects the promise with an error.
+  pub fn get_error_script(id: Value, error: RpcError) -> Result<String> {
+    Self::error_script(None, &RpcBytesStore::default(), &id, &error)
+  }
+
+  pub(crate) fn result_script(
+    codec: Option<&dyn RpcCodec>,
+    bytes: &RpcBytesStore,
+    id: &Value,
+    result: Value,
+  ) -> Result<String> {
+    client_call(codec, bytes, "_result", vec![id.clone(), result])
+  }
+
+  pub(crate) fn error_script(
+    codec: Option<&dyn RpcCodec>,
+    bytes: &RpcBytesStore,
+    id: &Value,
+    error: &RpcError,
+  ) -> Result<String> {
+    let error = serde_json::to_value(error)?;
+    client_call(codec, bytes, "_error", vec![id.clone(), error])
+  }
+}
+
+/// JSON-RPC error object sent back to Javascript when a call fails.
+///
+/// On the Javascript side, the promise of the call is rejected with a `window.rpc.RpcError`
+/// carrying the same `code`, `message` and `data`. The codes from `-32768` to `-32000` are
+/// reserved by the [JSON-RPC specification](https://www.jsonrpc.org/specification#error_object),
+/// the predefined ones are available as associated constants.
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
+#[error("{message} ({code})")]
+pub struct RpcError {
+  pub code: i64,
+  pub message: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub data: Option<Value>,
+}
+
+impl RpcError {
+  /// Invalid JSON was received.
+  pub const PARSE_ERROR: i64 = -32700;
+  /// The JSON sent is not a valid request object.
+  pub const INVALID_REQUEST: i64 = -32600;
+  /// The method does not exist or is not available.
+  pub const METHOD_NOT_FOUND: i64 = -32601;
+  /// Invalid method parameters.
+  pub const INVALID_PARAMS: i64 = -32602;
+  /// Internal JSON-RPC error.
+  pub const INTERNAL_ERROR: i64 = -32603;
+  /// A call the page gave up on, see [`CancellationToken`].
+  pub const CANCELLED: i64 = -32800;
+  /// Generic error of a method, used when a handler fails with an [`Error`] which isn't an
+  /// [`RpcError`].
+  pub const SERVER_ERROR: i64 = -32000;
+  /// A call which wasn't answered in time, see
+  /// [`WebViewBuilder::with_rpc_timeout`](crate::webview::WebViewBuilder::with_rpc_timeout).
+  pub const TIMEOUT: i64 = -32001;
+  /// A call to a method the origin of the page isn't allowed to call, see
+  /// [`WebViewBuilder::with_rpc_allowlist`](crate::webview::WebViewBuilder::with_rpc_allowlist).
+  pub const PERMISSION_DENIED: i64 = -32002;
+
+  /// Create an error with the given code and message.
+  pub fn new(code: i64, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+      data: None,
+    }
+  }
+
+  /// Attach additional information about the error.
+  pub fn with_data(mut self, data: Value) -> Self {
+    self.data = Some(data);
+    self
+  }
+
+  /// Create a [`RpcError::PARSE_ERROR`].
+  pub fn parse_error(message: impl Into<String>) -> Self {
+    Self::new(Self::PARSE_ERROR, message)
+  }
+
+  /// Create a [`RpcError::INVALID_REQUEST`].
+  pub fn invalid_request(message: impl Into<String>) -> Self {
+    Self::new(Self::INVALID_REQUEST, message)
+  }
+
+  /// Create a [`RpcError::METHOD_NOT_FOUND`] for the given method.
+  pub fn method_not_found(method: &str) -> Self {
+    Self::new(
+      Self::METHOD_NOT_FOUND,
+      format!("Method not found: {}", method),
+    )
+  }
+
+  /// Create a [`RpcError::INVALID_PARAMS`].
+  pub fn invalid_params(message: impl Into<String>) -> Self {
+    Self::new(Self::INVALID_PARAMS, message)
+  }
+
+  /// Create a [`RpcError::INTERNAL_ERROR`].
+  pub fn internal_error(message: impl Into<String>) -> Self {
+    Self::new(Self::INTERNAL_ERROR, message)
+  }
+
+  // Turn a message which isn't valid JSON into an error reply, as long as the id of the call can
+  // still be found in it.
+  pub(crate) fn from_message(js: &str, e: &serde_json::Error) -> Option<(Value, Self)> {
+    // Look for the first `"id":` key and read the value following it.
+    let start = js.find("\"id\"")? + 4;
+    let rest = js[start..].trim_start().strip_prefix(':')?;
+    let id = serde_json::Deserializer::from_str(rest)
+      .into_iter::<Value>()
+      .next()?
+      .ok()
+      .filter(is_valid_id)?;
+    Some((id, Self::parse_error(e.to_string())))
+  }
+}
+
+impl From<Error> for RpcError {
+  fn from(error: Error) -> Self {
+    match error {
+      Error::RpcError(error) => error,
+      error => Self::new(Self::SERVER_ERROR, error.to_string()),
+    }
+  }
+}
+
+pub(crate) fn is_valid_id(id: &Value) -> bool {
+  id.is_number() || id.is_string()
+}
+
+/// A handle to reply to a [`RpcRequest`] asynchronously.
+///
+/// The handler passed to [`WebViewBuilder::with_rpc_handler`](crate::webview::WebViewBuilder::with_rpc_handler)
+/// runs on the main thread and any work done there blocks the event loop. To reply later, take
+/// the responder with [`RpcRequest::responder`], return `None` from the handler and move the
+/// responder to wherever the work is done. Resolving or rejecting sends the script through the
+/// [`Dispatcher`] channel, so it runs once [`WebView::evaluate_script`](crate::webview::WebView::evaluate_script)
+/// is called on the main thread.
+#[derive(Clone)]
+pub struct RpcResponder {
+  id: Value,
+  dispatcher: Dispatcher,
+  cancelled: CancellationToken,
+}
+
+impl RpcResponder {
+  pub(crate) fn new(id: Value, dispatcher: Dispatcher, cancelled: CancellationToken) -> Self {
+    Self {
+      id,
+      dispatcher,
+      cancelled,
+    }
+  }
+
+  /// Resolve the pending promise of the call with a result.
+  pub fn resolve<T: Serialize>(&self, result: T) -> Result<()> {
+    let result = serde_json::to_value(result)?;
+    self.dispatcher.bytes.claim(Some(&self.id), &result);
+    let dispatcher = &self.dispatcher;
+    let js = RpcResponse::result_script(
+      dispatcher.codec.as_deref(),
+      &dispatcher.bytes,
+      &self.id,
+      result,
+    )?;
+    self.dispatcher.dispatch_script(&js)
+  }
+
+  /// Reject the pending promise of the call with an error.
+  pub fn reject(&self, error: RpcError) -> Result<()> {
+    let dispatcher = &self.dispatcher;
+    let js = RpcResponse::error_script(
+      dispatcher.codec.as_deref(),
+      &dispatcher.bytes,
+      &self.id,
+      &error,
+    )?;
+    self.dispatcher.dispatch_script(&js)
+  }
+
+  /// Get a [`CancellationToken`] telling whether the page gave up on the call.
+  pub fn cancellation_token(&self) -> CancellationToken {
+    self.cancelled.clone()
+  }
+}
+
+impl fmt::Debug for RpcResponder {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("RpcResponder")
+      .field("id", &self.id)
+      .finish()
+  }
+}
+
+/// A handle to send many results to a call made with `window.rpc.stream`.
+///
+/// On the Javascript side, `window.rpc.stream(method, ...params)` returns an async iterator which
+/// yields every item sent with [`RpcSink::send`] and ends once the sink is closed, either with
+/// [`RpcSink::close`] or by dropping it. Failing with [`RpcSink::error`] makes the iterator throw
+/// the error instead. Get the sink with [`RpcRequest::sink`], return `None` from the handler and
+/// move the sink to wherever the items are produced.
+///
+/// When the page stops iterating, for example by breaking out of a `for await` loop, the call is
+/// cancelled: [`RpcSink::is_cancelled`] returns `true` and [`RpcSink::send`] fails with a
+/// [`RpcError::CANCELLED`] error, so the producer can stop its work.
+#[derive(Debug)]
+pub struct RpcSink {
+  responder: RpcResponder,
+  closed: bool,
+}
+
+impl RpcSink {
+  fn new(responder: RpcResponder) -> Self {
+    Self {
+      responder,
+      closed: false,
+    }
+  }
+
+  /// Send an item to the iterator.
+  pub fn send<T: Serialize>(&self, item: T) -> Result<()> {
+    if self.is_cancelled() {
+      return Err(RpcError::new(RpcError::CANCELLED, "The stream was cancelled").into());
+    }
+    let item = serde_json::to_value(item)?;
+    let dispatcher = &self.responder.dispatcher;
+    dispatcher.bytes.claim(Some(&self.responder.id), &item);
+    let js = client_call(
+      dispatcher.codec.as_deref(),
+      &dispatcher.bytes,
+      "_next",
+      vec![self.responder.id.clone(), item],
+    )?;
+    dispatcher.dispatch_script(&js)
+  }
+
+  /// End the iterator after the items sent so far.
+  pub fn close(mut self) -> Result<()> {
+    self.closed = true;
+    self.responder.resolve(Value::Null)
+  }
+
+  /// End the iterator with an error, thrown once the items sent so far are consumed.
+  pub fn error(mut self, error: RpcError) -> Result<()> {
+    self.closed = true;
+    self.responder.reject(error)
+  }
+
+  /// Whether the page stopped iterating.
+  pub fn is_cancelled(&self) -> bool {
+    self.responder.cancelled.is_cancelled()
+  }
+}
+
+impl Drop for RpcSink {
+  fn drop(&mut self) {
+    if !self.closed && !self.is_cancelled() {
+      let _ = self.responder.resolve(Value::Null);
+    }
+  }
+}
+
+/// A [`RpcSink`] sending items of a single type, given to the methods registered with
+/// [`RpcRouter::with_typed_stream_method`] so the generated TypeScript knows what the iterator
+/// yields.
+#[derive(Debug)]
+pub struct TypedRpcSink<T> {
+  sink: RpcSink,
+  item: PhantomData<fn(T)>,
+}
+
+impl<T: Serialize> TypedRpcSink<T> {
+  /// Send an item to the iterator.
+  pub fn send(&self, item: T) -> Result<()> {
+    self.sink.send(item)
+  }
+
+  /// End the iterator after the items sent so far.
+  pub fn close(self) -> Result<()> {
+    self.sink.close()
+  }
+
+  /// End the iterator with an error, thrown once the items sent so far are consumed.
+  pub fn error(self, error: RpcError) -> Result<()> {
+    self.sink.error(error)
+  }
+
+  /// Whether the page stopped iterating.
+  pub fn is_cancelled(&self) -> bool {
+    self.sink.is_cancelled()
+  }
+
+  /// Get the untyped sink.
+  pub fn into_inner(self) -> RpcSink {
+    self.sink
+  }
+}
+
+/// A flag raised when the page gives up on a call.
+///
+/// The page cancels a call by aborting the `AbortSignal` passed as the last argument of
+/// `window.rpc.call`, or with the `signal` option of `window.rpc.callWithOptions`. The promise of
+/// the call is then rejected with a `window.rpc.RpcError` of code [`RpcError::CANCELLED`] and any
+/// reply sent afterwards is ignored. Handlers doing their work on another thread can check the
+/// token, taken with [`RpcRequest::cancellation_token`] or [`RpcResponder::cancellation_token`],
+/// to stop early.
+///
+/// ```no_run
+/// use wry::webview::RpcRequest;
+///
+/// fn handle(req: RpcRequest) {
+///   if let (Some(responder), Some(token)) = (req.responder(), req.cancellation_token()) {
+///     std::thread::spawn(move || {
+///       for _ in 0..100 {
+///         if token.is_cancelled() {
+///           return;
+///         }
+///         // Do a part of the work...
+///       }
+///       let _ = responder.resolve("done");
+///     });
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+  /// Whether the page gave up on the call.
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Cancellation tokens of the calls which may still be answered, by id.
+#[derive(Default)]
+pub(crate) struct Cancellations(Mutex<HashMap<String, CancellationToken>>);
+
+impl Cancellations {
+  /// Get the token of a new call.
+  pub(crate) fn register(&self, id: &Value) -> CancellationToken {
+    let mut tokens = self.0.lock().unwrap_or_else(|e| e.into_inner());
+    // Forget the calls without any responder left, nothing can check their token anymore.
+    tokens.retain(|_, token| Arc::strong_count(&token.0) > 1);
+    let token = CancellationToken::default();
+    tokens.insert(id.to_string(), token.clone());
+    token
+  }
+
+  /// Cancel a call the page gave up on.
+  pub(crate) fn cancel(&self, id: &Value) {
+    let token = self
+      .0
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .remove(&id.to_string());
+    if let Some(token) = token {
+      token.0.store(true, Ordering::Relaxed);
+    }
+  }
+}
+
+/// Origins allowed to send RPC requests, along with the methods each of them can call.
+#[derive(Clone, Default)]
+pub(crate) struct RpcAcl {
+  origins: Vec<(String, HashSet<String>)>,
+}
+
+impl RpcAcl {
+  /// Allow an origin, or a whole scheme like `app://`, to call the given methods.
+  pub(crate) fn allow(&mut self, origin: &str, methods: impl IntoIterator<Item = String>) {
+    let origin = if origin.ends_with("://") {
+      origin.to_string()
+    } else {
+      origin_of(origin).unwrap_or_else(|| origin.to_string())
+    };
+    match self.origins.iter_mut().find(|(o, _)| *o == origin) {
+      Some((_, allowed)) => allowed.extend(methods),
+      None => self.origins.push((origin, methods.into_iter().collect())),
+    }
+  }
+
+  /// Whether a page of the origin can call the method. Everything is allowed until an origin is
+  /// added to the list.
+  pub(crate) fn is_allowed(&self, origin: Option<&str>, method: &str) -> bool {
+    self.check(origin, |methods| {
+      methods.contains("*") || methods.contains(method)
+    })
+  }
+
+  /// Whether a page of the origin can call any method, which it needs to send the messages
+  /// without one: the replies to [`Dispatcher::call_js`] and the cancellations of its calls.
+  pub(crate) fn allows_origin(&self, origin: Option<&str>) -> bool {
+    self.check(origin, |methods| !methods.is_empty())
+  }
+
+  fn check<F: Fn(&HashSet<String>) -> bool>(&self, origin: Option<&str>, allows: F) -> bool {
+    if self.origins.is_empty() {
+      return true;
+    }
+    let origin = match origin {
+      Some(origin) => origin,
+      None => return false,
+    };
+    self.origins.iter().any(|(allowed, methods)| {
+      let matches = if allowed.ends_with("://") {
+        origin.starts_with(allowed.as_str())
+      } else {
+        origin == allowed
+      };
+      matches && allows(methods)
+    })
+  }
+}
+
+/// Get the origin of a page from its URL, as `scheme://host[:port]`. The port is left out when
+/// it's the default one of the scheme, and an empty host is `localhost` so the pages of a custom
+/// protocol have the same origin on every platform.
+pub(crate) fn origin_of(url: &str) -> Option<String> {
+  let url = Url::parse(url).ok()?;
+  if url.cannot_be_a_base() {
+    return None;
+  }
+  let host = url.host_str().filter(|host| !host.is_empty());
+  let mut origin = format!("{}://{}", url.scheme(), host.unwrap_or("localhost"));
+  if let Some(port) = url.port() {
+    origin.push_str(&format!(":{}", port));
+  }
+  Some(origin)
+}
+
+pub(crate) type RpcInterceptor = Box<dyn Fn(&Window, RpcRequest, RpcNext) -> Option<RpcResponse>>;
+
+/// The rest of the chain of RPC interceptors, ending with the handler, passed to each interceptor
+/// registered with [`WebViewBuilder::with_rpc_interceptor`].
+///
+/// [`WebViewBuilder::with_rpc_interceptor`]: crate::webview::WebViewBuilder::with_rpc_interceptor
+pub struct RpcNext<'a> {
+  interceptors: &'a [RpcInterceptor],
+  handler: &'a dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>,
+}
+
+impl<'a> RpcNext<'a> {
+  /// Pass the request to the next interceptor, or to the handler after the last one, and get the
+  /// response it replies with.
+  pub fn run(self, window: &Window, req: RpcRequest) -> Option<RpcResponse> {
+    match self.interceptors.split_first() {
+      Some((interceptor, interceptors)) => interceptor(
+        window,
+        req,
+        RpcNext {
+          interceptors,
+          handler: self.handler,
+        },
+      ),
+      None => (self.handler)(window, req),
+    }
+  }
+}
+
+/// The RPC handler of a [`WebView`](crate::webview::WebView) along with the channel to reply to
+/// the requests asynchronously.
+pub(crate) struct RpcHandler {
+  pub(crate) handler: Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>,
+  pub(crate) interceptors: Vec<RpcInterceptor>,
+  pub(crate) dispatcher: Dispatcher,
+  pub(crate) cancellations: Cancellations,
+  pub(crate) acl: RpcAcl,
+  pub(crate) panic_hook: Option<PanicHook>,
+  pub(crate) error_handler: Option<ErrorHandler>,
+}
+
+impl RpcHandler {
+  /// Pass a request through the interceptors and the handler.
+  pub(crate) fn handle(&self, window: &Window, req: RpcRequest) -> Option<RpcResponse> {
+    RpcNext {
+      interceptors: &self.interceptors,
+      handler: &self.handler,
+    }
+    .run(window, req)
+  }
+}
+
+type JsCallback = Box<dyn FnOnce(Result<Value>) + Send>;
+
+/// Calls made from Rust to the functions registered with `window.rpc.register`, waiting for the
+/// page to reply.
+///
+/// Every callback runs exactly once: with the reply of the page, with an error when the page
+/// navigates away or the webview is dropped first, or with a [`RpcError::TIMEOUT`] error once the
+/// timeout of the calls is over.
+#[derive(Clone, Default)]
+pub(crate) struct JsCalls(Arc<JsCallsInner>);
+
+#[derive(Default)]
+struct JsCallsInner {
+  state: Mutex<JsCallsState>,
+  // Deadlines of the calls, sent to the thread failing them, started by the first call with a
+  // timeout
+  timer: OnceCell<Mutex<Sender<(Instant, String)>>>,
+}
+
+#[derive(Default)]
+struct JsCallsState {
+  // The ids can't be guessed, so only the page the call was sent to can reply to it
+  pending: HashMap<String, JsCallback>,
+  timeout: Option<Duration>,
+  // Why the calls fail right away, once no reply can come anymore
+  closed: Option<&'static str>,
+}
+
+impl JsCalls {
+  fn state(&self) -> MutexGuard<'_, JsCallsState> {
+    self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Set how long the calls wait for a reply, `None` to wait until the page navigates away.
+  pub(crate) fn set_timeout(&self, timeout: Option<Duration>) {
+    self.state().timeout = timeout;
+  }
+
+  /// Register the callback of a new call and get the script invoking the function.
+  ///
+  /// Returns `None` after running the callback with an error if no reply can come anymore.
+  pub(crate) fn call<P, F>(
+    &self,
+    codec: Option<&dyn RpcCodec>,
+    bytes: &RpcBytesStore,
+    method: &str,
+    params: P,
+    callback: F,
+  ) -> Result<Option<String>>
+  where
+    P: Serialize,
+    F: FnOnce(Result<Value>) + Send + 'static,
+  {
+    let params = serde_json::to_value(params)?;
+    bytes.claim(None, &params);
+    let id = new_token();
+    let script = client_call(
+      codec,
+      bytes,
+      "_invoke",
+      vec![id.clone().into(), method.into(), params],
+    )?;
+    let mut state = self.state();
+    if let Some(reason) = state.closed {
+      drop(state);
+      callback(Err(RpcError::new(RpcError::CANCELLED, reason).into()));
+      return Ok(None);
+    }
+    state.pending.insert(id.clone(), Box::new(callback));
+    let timeout = state.timeout;
+    drop(state);
+    if let Some(timeout) = timeout {
+      self.schedule(Instant::now() + timeout, id);
+    }
+    Ok(Some(script))
+  }
+
+  /// Pass the reply of the page to the callback of the call it belongs to.
+  pub(crate) fn settle(&self, mut response: RpcResponse, panic_hook: Option<&PanicHook>) {
+    let callback = response
+      .id
+      .as_ref()
+      .and_then(Value::as_str)
+      .and_then(|id| self.state().pending.remove(id));
+    if let Some(callback) = callback {
+      let result = match response.error.take() {
+        Some(error) => Err(error.into()),
+        None => Ok(response.result.take().unwrap_or(Value::Null)),
+      };
+      run_callback(callback, result, panic_hook);
+    }
+  }
+
+  /// Fail the pending calls, which the next page can't reply to.
+  pub(crate) fn navigated(&self, panic_hook: Option<&PanicHook>) {
+    self.reject_all("The page navigated away", panic_hook);
+  }
+
+  /// Fail the pending calls and the ones made from now on, when no reply can come anymore.
+  pub(crate) fn close(&self, reason: &'static str, panic_hook: Option<&PanicHook>) {
+    self.state().closed = Some(reason);
+    self.reject_all(reason, panic_hook);
+  }
+
+  fn reject_all(&self, reason: &str, panic_hook: Option<&PanicHook>) {
+    let pending = mem::take(&mut self.state().pending);
+    for (_, callback) in pending {
+      let error = RpcError::new(RpcError::CANCELLED, reason);
+      run_callback(callback, Err(error.into()), panic_hook);
+    }
+  }
+
+  fn schedule(&self, deadline: Instant, id: String) {
+    let timer = self.0.timer.get_or_init(|| {
+      let (tx, rx) = mpsc::channel();
+      let calls = Arc::downgrade(&self.0);
+      thread::spawn(move || expire_calls(calls, rx));
+      Mutex::new(tx)
+    });
+    let _ = timer
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .send((deadline, id));
+  }
+}
+
+// Fail the calls which aren't answered before their deadline, until the calls are dropped.
+fn expire_calls(calls: Weak<JsCallsInner>, rx: Receiver<(Instant, String)>) {
+  let mut deadlines: BinaryHeap<Reverse<(Instant, String)>> = BinaryHeap::new();
+  loop {
+    let received = match deadlines.peek() {
+      Some(Reverse((deadline, _))) => {
+        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+      }
+      None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    match received {
+      Ok(deadline) => deadlines.push(Reverse(deadline)),
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) => return,
+    }
+
+    let now = Instant::now();
+    while let Some(Reverse((deadline, _))) = deadlines.peek() {
+      if *deadline > now {
+        break;
+      }
+      let id = match deadlines.pop() {
+        Some(Reverse((_, id))) => id,
+        None => break,
+      };
+      let calls = match calls.upgrade() {
+        Some(calls) => JsCalls(calls),
+        None => return,
+      };
+      let callback = calls.state().pending.remove(&id);
+      if let Some(callback) = callback {
+        // The panic hook can't be called from this thread, the panic is only logged
+        let error = RpcError::new(RpcError::TIMEOUT, "The page didn't reply in time");
+        run_callback(callback, Err(error.into()), None);
+      }
+    }
+  }
+}
+
+// Run the callback of a call, which must not unwind through the callback of the web engine it
+// runs in.
+fn run_callback(callback: JsCallback, result: Result<Value>, panic_hook: Option<&PanicHook>) {
+  if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| callback(result))) {
+    let panic = HandlerPanic::Rpc {
+      method: None,
+      message: super::panic_message(&*payload),
+    };
+    super::report_panic(panic_hook, &panic);
+  }
+}
+
+/// Get the script of the RPC client exposed as `window.rpc`.
+pub(crate) fn client_script(timeout: Option<Duration>, codec: Option<&dyn RpcCodec>) -> String {
+  format!(
+    "{}\nwindow.external.rpc.timeout = {};\nwindow.external.rpc._bytesUrl = {:?};\nwindow.external.rpc._codec = {};",
+    include_str!("rpc.js"),
+    timeout.map(|timeout| timeout.as_millis()).unwrap_or(0),
+    RPC_PROTOCOL_URL,
+    codec
+      .map(|codec| codec.client_script())
+      .unwrap_or_else(|| "null".into())
+  )
+}
+
+/// Get a script calling the listeners of an event with its payload.
+pub(crate) fn emit_script<T: Serialize>(
+  codec: Option<&dyn RpcCodec>,
+  bytes: &RpcBytesStore,
+  event: &str,
+  payload: T,
+) -> Result<String> {
+  let payload = serde_json::to_value(payload)?;
+  bytes.claim(None, &payload);
+  client_call(codec, bytes, "_emit", vec![event.into(), payload])
+}
+
+/// Get a script calling a private function of the RPC client with some arguments. With a codec,
+/// the function and its arguments are packed in bytes the client fetches from the RPC protocol.
+pub(crate) fn client_call(
+  codec: Option<&dyn RpcCodec>,
+  bytes: &RpcBytesStore,
+  function: &str,
+  args: Vec<Value>,
+) -> Result<String> {
+  match codec {
+    Some(codec) => {
+      let mut message = vec![Value::from(function)];
+      message.extend(args);
+      let token = bytes.put(codec.encode(&Value::Array(message))?);
+      Ok(format!("window.external.rpc._receive({})", to_js(&token)?))
+    }
+    None => {
+      let args = args.iter().map(to_js).collect::<Result<Vec<_>>>()?;
+      Ok(format!(
+        "window.external.rpc.{}({})",
+        function,
+        args.join(", ")
+      ))
+    }
+  }
+}
+
+// Prefix of the messages the page packed with the codec, followed by the token of their bytes.
+const PACKED_PREFIX: &str = "packed:";
+
+/// Serialization format of the RPC messages exchanged with the page, replacing JSON.
+///
+/// Every message is JSON by default. A codec packs the messages in bytes instead, which go through
+/// the custom protocol of the [`RpcBytes`] rather than being encoded as text. The page packs and
+/// unpacks them with the Javascript counterpart of the codec, given by
+/// [`RpcCodec::client_script`]. Either way, the handlers still receive and return [`serde`] types,
+/// and the page uses the same `window.rpc` API. Set the codec with
+/// [`WebViewBuilder::with_rpc_codec`](crate::webview::WebViewBuilder::with_rpc_codec).
+///
+/// With the `msgpack` feature, [`MessagePackCodec`] packs the messages with
+/// [MessagePack](https://msgpack.org), which is more compact for large structured payloads.
+///
+/// Platform-specific behavior:
+///
+/// - **Linux:** Sending packed messages from Javascript requires WebKitGTK 2.36 or newer.
+pub trait RpcCodec: Send + Sync {
+  /// Pack a message sent to the page.
+  fn encode(&self, message: &Value) -> Result<Vec<u8>>;
+
+  /// Unpack a message sent by the page.
+  fn decode(&self, bytes: &[u8]) -> Result<Value>;
+
+  /// Get a Javascript expression evaluating to an object with an `encode(message)` function
+  /// returning a `Uint8Array`, and a `decode(bytes)` function taking one, which pack and unpack
+  /// the messages in the page.
+  fn client_script(&self) -> String;
+}
+
+/// A [`RpcCodec`] packing the messages with [MessagePack](https://msgpack.org).
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl RpcCodec for MessagePackCodec {
+  fn encode(&self, message: &Value) -> Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec_named(message)?)
+  }
+
+  fn decode(&self, bytes: &[u8]) -> Result<Value> {
+    Ok(rmp_serde::from_slice(bytes)?)
+  }
+
+  fn client_script(&self) -> String {
+    include_str!("msgpack.js").into()
+  }
+}
+
+/// Take and decode a message the page packed with the codec, or return `None` if it's JSON.
+pub(crate) fn unpack_message(
+  codec: Option<&dyn RpcCodec>,
+  bytes: &RpcBytesStore,
+  js: &str,
+) -> Option<Result<Value>> {
+  let token = js.strip_prefix(PACKED_PREFIX)?;
+  let message = match (codec, bytes.take(token)) {
+    (Some(codec), Some(bytes)) => codec.decode(&bytes),
+    (None, _) => Err(Error::RpcScriptError(
+      "The webview has no RPC codec".into(),
+      js.to_string(),
+    )),
+    (_, None) => Err(Error::RpcScriptError(
+      "No bytes for the packed message".into(),
+      js.to_string(),
+    )),
+  };
+  Some(message)
+}
+
+// Serialize a value to a JSON literal that can be embedded in a script. JSON allows the line and
+// paragraph separators in strings but older Javascript engines don't, so escape them as well.
+fn to_js<T: Serialize>(value: &T) -> Result<String> {
+  Ok(
+    serde_json::to_string(value)?
+      .replace('\u{2028}', "\\u2028")
+      .replace('\u{2029}', "\\u2029"),
+  )
+}
+
+// How long bytes wait to be taken by the other side, and how many of them wait at most for each
+// webview. The bytes of values which are serialized but never sent, or of calls the page never
+// finishes, would pile up otherwise.
+const BYTES_TTL: Duration = Duration::from_secs(60);
+const BYTES_CAP: usize = 256 * 1024 * 1024;
+
+// The bytes exchanged with the pages, by token, until the other side takes them.
+static RPC_BYTES: Lazy<Mutex<BytesPool>> = Lazy::new(Default::default);
+
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
+struct BytesPool {
+  entries: HashMap<String, BytesEntry>,
+}
+
+struct BytesEntry {
+  bytes: Vec<u8>,
+  // The webview the bytes are exchanged with and the call they belong to, once known
+  store: Option<u64>,
+  call: Option<Value>,
+  // Whether the bytes were uploaded for a message the webview received, and can be deserialized
+  resolved: bool,
+  expires: Instant,
+}
+
+impl BytesPool {
+  fn lock() -> MutexGuard<'static, Self> {
+    RPC_BYTES.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn insert(&mut self, token: String, bytes: Vec<u8>, store: Option<u64>, call: Option<Value>) {
+    let now = Instant::now();
+    self.entries.retain(|_, entry| entry.expires > now);
+    self.entries.insert(
+      token,
+      BytesEntry {
+        bytes,
+        store,
+        call,
+        resolved: false,
+        expires: now + BYTES_TTL,
+      },
+    );
+    // Drop the oldest bytes of the webview first, which are the closest to expire, so a page
+    // can't evict the bytes of the others
+    loop {
+      let mut size = 0;
+      let mut oldest: Option<(&String, Instant)> = None;
+      for (token, entry) in self
+        .entries
+        .iter()
+        .filter(|(_, entry)| entry.store == store)
+      {
+        size += entry.bytes.len();
+        match oldest {
+          Some((_, expires)) if expires <= entry.expires => {}
+          _ => oldest = Some((token, entry.expires)),
+        }
+      }
+      match oldest {
+        Some((token, _)) if size > BYTES_CAP => {
+          let token = token.clone();
+          self.entries.remove(&token);
+        }
+        _ => break,
+      }
+    }
+  }
+
+  fn remove(&mut self, token: &str) -> Option<Vec<u8>> {
+    self.entries.remove(token).map(|entry| entry.bytes)
+  }
+}
+
+/// Get a token which can't be guessed by the pages, from the random number generator of the OS.
+pub(crate) fn new_token() -> String {
+  let mut bytes = [0; 16];
+  getrandom::getrandom(&mut bytes).expect("Failed to get random bytes from the OS");
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The [`RpcBytes`] exchanged with a webview.
+///
+/// The bytes sent to the page belong to the webview and to the call they answer once the reply
+/// is sent, and the ones uploaded by the page belong to the webview and to the call they're the
+/// parameters of. They're dropped once taken, when the call is cancelled or timed out, when the
+/// page navigates away and when the webview is dropped.
+#[derive(Clone)]
+pub(crate) struct RpcBytesStore(u64);
+
+impl Default for RpcBytesStore {
+  fn default() -> Self {
+    Self(NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed))
+  }
+}
+
+impl RpcBytesStore {
+  /// Take the ownership of the bytes referenced by a value sent to the page, for the call `id`.
+  pub(crate) fn claim(&self, id: Option<&Value>, value: &Value) {
+    fn visit(value: &Value, tokens: &mut Vec<String>) {
+      match value {
+        Value::Array(items) => items.iter().for_each(|item| visit(item, tokens)),
+        Value::Object(map) => match (map.len(), map.get(BYTES_REF)) {
+          (1, Some(Value::String(token))) => tokens.push(token.clone()),
+          _ => map.values().for_each(|item| visit(item, tokens)),
+        },
+        _ => {}
+      }
+    }
+    let mut tokens = Vec::new();
+    visit(value, &mut tokens);
+    if tokens.is_empty() {
+      return;
+    }
+    let mut pool = BytesPool::lock();
+    for token in tokens {
+      if let Some(entry) = pool.entries.get_mut(&token) {
+        if entry.store.is_none() {
+          entry.store = Some(self.0);
+          entry.call = id.cloned();
+        }
+      }
+    }
+  }
+
+  /// Keep bytes for the page to fetch, returning their token.
+  pub(crate) fn put(&self, bytes: Vec<u8>) -> String {
+    let token = new_token();
+    BytesPool::lock().insert(token.clone(), bytes, Some(self.0), None);
+    token
+  }
+
+  /// Take bytes the page uploaded.
+  pub(crate) fn take(&self, token: &str) -> Option<Vec<u8>> {
+    let mut pool = BytesPool::lock();
+    match pool.entries.get(token) {
+      Some(entry) if entry.store == Some(self.0) => pool.remove(token),
+      _ => None,
+    }
+  }
+
+  /// Drop the bytes of a call the page gave up on.
+  pub(crate) fn release(&self, id: &Value) {
+    BytesPool::lock()
+      .entries
+      .retain(|_, entry| entry.store != Some(self.0) || entry.call.as_ref() != Some(id));
+  }
+
+  /// Drop all the bytes of the webview, which the next page can't take.
+  pub(crate) fn clear(&self) {
+    BytesPool::lock()
+      .entries
+      .retain(|_, entry| entry.store != Some(self.0));
+  }
+
+  /// Give the bytes the page uploaded for a message it sent new tokens, which the [`RpcBytes`] of
+  /// the message deserialize from. The tokens of the bytes of other webviews are left as they are
+  /// and fail to deserialize.
+  pub(crate) fn resolve(&self, value: &mut Value) {
+    fn visit(store: u64, pool: &mut BytesPool, value: &mut Value) {
+      match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| visit(store, pool, item)),
+        Value::Object(map) if map.len() == 1 && map.contains_key(BYTES_REF) => {
+          if let Some(Value::String(token)) = map.get_mut(BYTES_REF) {
+            let owned = matches!(
+              pool.entries.get(token.as_str()),
+              Some(entry) if entry.store == Some(store) && !entry.resolved
+            );
+            if let Some(mut entry) = pool.entries.remove(token.as_str()) {
+              if owned {
+                entry.resolved = true;
+                *token = new_token();
+              }
+              pool.entries.insert(token.clone(), entry);
+            }
+          }
+        }
+        Value::Object(map) => map.values_mut().for_each(|item| visit(store, pool, item)),
+        _ => {}
+      }
+    }
+    visit(self.0, &mut BytesPool::lock(), value);
+  }
+
+  /// Handle the requests of the custom protocol the page exchanges [`RpcBytes`] with.
+  ///
+  /// `GET /<token>` takes bytes sent to the page and `POST /<token>?id=<call>` uploads bytes for
+  /// the parameters of a call. Only the origins allowed to make RPC calls can do either.
+  pub(crate) fn respond(&self, request: &Request, acl: &RpcAcl) -> Result<Response> {
+    let origin = request
+      .headers()
+      .get(header::ORIGIN)
+      .and_then(|origin| origin.to_str().ok())
+      .and_then(origin_of);
+    if !acl.allows_origin(origin.as_deref()) {
+      return Ok(Response::new(vec![]).with_status(StatusCode::FORBIDDEN));
+    }
+    let url = Url::parse(request.uri())?;
+    let token = url
+      .path_segments()
+      .and_then(|mut segments| segments.next_back())
+      .unwrap_or_default()
+      .to_string();
+    let mut pool = BytesPool::lock();
+    let response = match *request.method() {
+      Method::GET => match pool.entries.get(&token) {
+        Some(entry)
+          if (entry.store.is_none() || entry.store == Some(self.0)) && !entry.resolved =>
+        {
+          let bytes = pool.remove(&token).unwrap_or_default();
+          Response::new(bytes).with_mimetype("application/octet-stream")
+        }
+        _ => Response::new(vec![]).with_status(StatusCode::NOT_FOUND),
+      },
+      // Tokens are chosen by the page, which must not replace the bytes of another one
+      Method::POST if token.len() >= 32 && !pool.entries.contains_key(&token) => {
+        let call = url
+          .query_pairs()
+          .find(|(name, _)| name == "id")
+          .map(|(_, id)| Value::String(id.into_owned()));
+        pool.insert(token, request.body().to_vec(), Some(self.0), call);
+        Response::new(vec![]).with_status(StatusCode::NO_CONTENT)
+      }
+      Method::POST => Response::new(vec![]).with_status(StatusCode::CONFLICT),
+      _ => Response::new(vec![]).with_status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    // The pages fetch the bytes from their own origin
+    Ok(
+      match origin.and_then(|origin| HeaderValue::from_str(&origin).ok()) {
+        Some(origin) => response
+          .with_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+          .with_header(header::VARY, HeaderValue::from_static("Origin")),
+        None => response,
+      },
+    )
+  }
+}
+
+const BYTES_REF: &str = "__wry_bytes_ref__";
+
+/// Binary data passed to or returned from an RPC method.
+///
+/// JSON has no binary type, so a `Vec<u8>` would be sent as an array of numbers. Wrapping the
+/// bytes in [`RpcBytes`] instead lets them go through a custom protocol internal to the RPC
+/// client, outside of the messages which only carry a token: the bytes returned to Javascript
+/// arrive as a `Uint8Array`, and any `ArrayBuffer` or typed array in the parameters of a call is
+/// uploaded by the page and deserializes to [`RpcBytes`].
+///
+/// Serializing an [`RpcBytes`] keeps a copy of the bytes until the page fetches them, so it
+/// should only be done to send them back to Javascript. The bytes nobody takes are dropped after
+/// a minute, and the oldest ones are dropped first once those of a webview take more than
+/// 256 MiB.
+///
+/// Platform-specific behavior:
+///
+/// - **Linux:** Receiving bytes from Javascript requires WebKitGTK 2.36 or newer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RpcBytes(pub Vec<u8>);
+
+impl From<Vec<u8>> for RpcBytes {
+  fn from(bytes: Vec<u8>) -> Self {
+    Self(bytes)
+  }
+}
+
+impl From<RpcBytes> for Vec<u8> {
+  fn from(bytes: RpcBytes) -> Self {
+    bytes.0
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RpcBytesRepr {
+  Token {
+    #[serde(rename = "__wry_bytes_ref__")]
+    token: String,
+  },
+  Array(Vec<u8>),
+}
+
+impl Serialize for RpcBytes {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let token = new_token();
+    BytesPool::lock().insert(token.clone(), self.0.clone(), None, None);
+    RpcBytesRepr::Token { token }.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for RpcBytes {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    use serde::de::Error;
+    match RpcBytesRepr::deserialize(deserializer)? {
+      RpcBytesRepr::Token { token } => {
+        // Only the bytes not sent yet, and the ones uploaded for a message once it's received by
+        // the webview it was sent to
+        let mut pool = BytesPool::lock();
+        match pool.entries.get(&token) {
+          Some(entry) if entry.resolved || entry.store.is_none() => pool.remove(&token).map(Self),
+          _ => None,
+        }
+        .ok_or_else(|| D::Error::custom(format!("no bytes for token {}", token)))
+      }
+      RpcBytesRepr::Array(bytes) => Ok(Self(bytes)),
+    }
+  }
+}
+
+type RpcMethod =
+  Box<dyn Fn(&Window, &RpcRequest, Value) -> std::result::Result<Option<Value>, RpcError>>;
+
+/// A set of named RPC methods with typed parameters and results.
+///
+/// Every method is registered with a closure taking the [`Window`] and a parameter type which
+/// is deserialized from the `params` of the request. Since `window.rpc.call('name', a, b)` sends
+/// its arguments as an array, the parameter type is usually a tuple like `(A, B)`. The returned
+/// value is serialized as the result of the call. Requests to unknown methods or with parameters
+/// that fail to deserialize are answered with the standard JSON-RPC errors automatically.
+///
+/// Methods registered with [`RpcRouter::with_stream_method`] send their results through a
+/// [`RpcSink`] instead, to be called with `window.rpc.stream`.
+///
+/// Methods registered with [`RpcRouter::with_typed_method`] also record their parameter and
+/// result types, so [`RpcRouter::typescript_definitions`] can describe them to the front-end.
+///
+/// Pass the router to [`WebViewBuilder::with_rpc_router`](crate::webview::WebViewBuilder::with_rpc_router)
+/// to use it as the RPC handler of a [`WebView`](crate::webview::WebView).
+///
+/// ```no_run
+/// use wry::webview::RpcRouter;
+///
+/// let router = RpcRouter::new()
+///   .with_method("add", |_window, (a, b): (i64, i64)| Ok(a + b))
+///   .with_method("greet", |_window, (name,): (String,)| {
+///     Ok(format!("Hello, {}!", name))
+///   });
+/// ```
+pub struct RpcRouter {
+  methods: HashMap<String, RpcMethod>,
+  signatures: BTreeMap<String, TsSignature>,
+}
+
+impl RpcRouter {
+  /// Create an empty [`RpcRouter`].
+  pub fn new() -> Self {
+    Self {
+      methods: HashMap::new(),
+      signatures: BTreeMap::new(),
+    }
+  }
+
+  /// Register a method under the given name. Registering the same name twice replaces the
+  /// previous method.
+  ///
+  /// An `Err` returned by the closure is sent back to Javascript as an error response, which
+  /// rejects the promise returned by `window.rpc.call`. Return an [`Error::RpcError`] to choose
+  /// the code of the error, any other error is sent as a [`RpcError::SERVER_ERROR`].
+  pub fn with_method<P, R, F>(mut self, name: &str, method: F) -> Self
+  where
+    P: DeserializeOwned,
+    R: Serialize,
+    F: Fn(&Window, P) -> Result<R> + 'static,
+  {
+    let method = move |window: &Window, _: &RpcRequest, params: Value| {
+      let result = method(window, typed_params(params)?)?;
+      serde_json::to_value(result)
+        .map(Some)
+        .map_err(|e| RpcError::internal_error(e.to_string()))
+    };
+    self.methods.insert(name.to_string(), Box::new(method));
+    self
+      .signatures
+      .insert(name.to_string(), TsSignature::Untyped { stream: false });
+    self
+  }
+
+  /// Same as [`RpcRouter::with_method`], also recording the parameter and result types of the
+  /// method for [`RpcRouter::typescript_definitions`] and [`RpcRouter::typescript_client`].
+  pub fn with_typed_method<P, R, F>(self, name: &str, method: F) -> Self
+  where
+    P: DeserializeOwned + TsType,
+    R: Serialize + TsType,
+    F: Fn(&Window, P) -> Result<R> + 'static,
+  {
+    let mut router = self.with_method(name, method);
+    router.signatures.insert(
+      name.to_string(),
+      TsSignature::Method {
+        params: P::ts_params(),
+        result: R::ts_type(),
+      },
+    );
+    router
+  }
+
+  /// Register a method streaming its results to `window.rpc.stream` under the given name.
+  ///
+  /// The closure receives the [`RpcSink`] of the call along with the parameters. It usually moves
+  /// the sink to another thread and returns right away, the stream ending once the sink is
+  /// dropped. An `Err` returned by the closure ends the stream with that error instead.
+  pub fn with_stream_method<P, F>(mut self, name: &str, method: F) -> Self
+  where
+    P: DeserializeOwned,
+    F: Fn(&Window, P, RpcSink) -> Result<()> + 'static,
+  {
+    let method = move |window: &Window, req: &RpcRequest, params: Value| {
+      let params = typed_params(params)?;
+      let sink = req
+        .sink()
+        .ok_or_else(|| RpcError::invalid_request("A stream can't be a notification"))?;
+      method(window, params, sink)?;
+      Ok(None)
+    };
+    self.methods.insert(name.to_string(), Box::new(method));
+    self
+      .signatures
+      .insert(name.to_string(), TsSignature::Untyped { stream: true });
+    self
+  }
+
+  /// Same as [`RpcRouter::with_stream_method`] with a [`TypedRpcSink`], also recording the
+  /// parameter and item types of the stream for [`RpcRouter::typescript_definitions`] and
+  /// [`RpcRouter::typescript_client`].
+  pub fn with_typed_stream_method<P, T, F>(self, name: &str, method: F) -> Self
+  where
+    P: DeserializeOwned + TsType,
+    T: Serialize + TsType,
+    F: Fn(&Window, P, TypedRpcSink<T>) -> Result<()> + 'static,
+  {
+    let mut router = self.with_stream_method(name, move |window, params, sink| {
+      let sink = TypedRpcSink {
+        sink,
+        item: PhantomData,
+      };
+      method(window, params, sink)
+    });
+    router.signatures.insert(
+      name.to_string(),
+      TsSignature::Stream {
+        params: P::ts_params(),
+        item: T::ts_type(),
+      },
+    );
+    router
+  }
+
+  /// Get a TypeScript declaration file describing the registered methods, to keep the calls made
+  /// by the front-end in sync with Rust.
+  ///
+  /// It declares a `RpcMethods` interface with the signature of every method, a `RpcStreams` one
+  /// for the stream methods, and types `window.rpc` with them so `tsc` checks the name, the
+  /// arguments and the result of each `window.rpc.call`. Only the methods registered with
+  /// [`RpcRouter::with_typed_method`] or [`RpcRouter::with_typed_stream_method`] have their
+  /// types, the others take and return anything.
+  pub fn typescript_definitions(&self) -> String {
+    typescript::definitions(&self.signatures)
+  }
+
+  /// Get a TypeScript module exporting a typed function for each registered method, named after
+  /// the method in camel case, like `sendParameters` for `send-parameters`.
+  ///
+  /// Methods whose names give the same function name are numbered in the order of their names,
+  /// like `aB` and `aB2` for `a-b` and `a.b`. A name without any letter or digit gives `method`,
+  /// and a reserved word like `delete` gets a trailing underscore.
+  pub fn typescript_client(&self) -> String {
+    typescript::client(&self.signatures)
+  }
+
+  /// Handle a request with the registered methods. This has the same signature as the closure
+  /// expected by [`WebViewBuilder::with_rpc_handler`](crate::webview::WebViewBuilder::with_rpc_handler).
+  ///
+  /// Returns `None` if the request is a notification, which never gets a reply.
+  pub fn handle(&self, window: &Window, mut req: RpcRequest) -> Option<RpcResponse> {
+    let params = req.params.take().unwrap_or(Value::Null);
+    let result = self
+      .method(&req.method)
+      .and_then(|method| method(window, &req, params));
+
+    let id = req.id.take()?;
+    match result {
+      Ok(Some(result)) => Some(RpcResponse::new_result(Some(id), Some(result))),
+      // Streams are answered through their sink
+      Ok(None) => None,
+      Err(error) => Some(RpcResponse::new_error(Some(id), error)),
+    }
+  }
+
+  // Get the method registered under a name, or the error answering the calls to unknown methods.
+  fn method(&self, name: &str) -> std::result::Result<&RpcMethod, RpcError> {
+    self
+      .methods
+      .get(name)
+      .ok_or_else(|| RpcError::method_not_found(name))
+  }
+}
+
+// Deserialize the parameters of a call, or get the error answering the calls with invalid ones.
+fn typed_params<P: DeserializeOwned>(params: Value) -> std::result::Result<P, RpcError> {
+  parse_params(params).map_err(|e| RpcError::invalid_params(e.to_string()))
+}
+
+// Javascript always sends the arguments as an array, so an empty call like `rpc.call('quit')`
+// arrives as `[]`. Let that also match parameter types which expect nothing, such as `()`.
+fn parse_params<P: DeserializeOwned>(params: Value) -> serde_json::Result<P> {
+  let empty = match &params {
+    Value::Array(array) => array.is_empty(),
+    Value::Null => true,
+    _ => false,
+  };
+  match serde_json::from_value(params) {
+    Err(_) if empty => serde_json::from_value(Value::Null),
+    result => result,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn should_recover_id_from_bad_message() {
+    let js = r#"{"jsonrpc":"2.0","id":"abc","method":"add","params":[1,}"#;
+    let e = serde_json::from_str::<Value>(js).unwrap_err();
+    let (id, error) = RpcError::from_message(js, &e).unwrap();
+    assert_eq!(id, Value::from("abc"));
+    assert_eq!(error.code, RpcError::PARSE_ERROR);
+
+    let js = r#"{"jsonrpc":"2.0","id" : 7,"method":"add""#;
+    let e = serde_json::from_str::<Value>(js).unwrap_err();
+    let (id, _) = RpcError::from_message(js, &e).unwrap();
+    assert_eq!(id, Value::from(7));
+
+    let js = r#"{"jsonrpc":"2.0","method":"add","params":[1,}"#;
+    let e = serde_json::from_str::<Value>(js).unwrap_err();
+    assert!(RpcError::from_message(js, &e).is_none());
+  }
+
+  #[test]
+  fn should_map_router_errors() {
+    assert_eq!(typed_params::<(i64, i64)>(json!([1, 2])).unwrap(), (1, 2));
+    assert_eq!(
+      typed_params::<Vec<i64>>(json!([])).unwrap(),
+      Vec::<i64>::new()
+    );
+    // An empty call matches the parameter types which expect nothing
+    assert!(typed_params::<()>(json!([])).is_ok());
+    assert!(typed_params::<()>(Value::Null).is_ok());
+    assert_eq!(typed_params::<Option<i64>>(json!([])).unwrap(), None);
+
+    let error = typed_params::<(i64, i64)>(json!([1, "two"])).unwrap_err();
+    assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    let error = typed_params::<(i64,)>(json!([])).unwrap_err();
+    assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    let error = typed_params::<()>(json!([1])).unwrap_err();
+    assert_eq!(error.code, RpcError::INVALID_PARAMS);
+
+    let router = RpcRouter::new().with_method("add", |_, (a, b): (i64, i64)| Ok(a + b));
+    assert!(router.method("add").is_ok());
+    assert_eq!(
+      router.method("sub").err().map(|error| error.code),
+      Some(RpcError::METHOD_NOT_FOUND)
+    );
+
+    let error = RpcError::from(Error::RpcError(RpcError::invalid_params("no")));
+    assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    let error = RpcError::from(Error::from(std::io::Error::from(
+      std::io::ErrorKind::NotFound,
+    )));
+    assert_eq!(error.code, RpcError::SERVER_ERROR);
+  }
+
+  #[test]
+  fn should_pass_bytes_by_token() {
+    let acl = RpcAcl::default();
+    let store = RpcBytesStore::default();
+    let other = RpcBytesStore::default();
+    let value = serde_json::to_value(RpcBytes(vec![1, 2, 3])).unwrap();
+    let token = value["__wry_bytes_ref__"].as_str().unwrap().to_string();
+    store.claim(Some(&json!(1)), &json!([value]));
+    let get = Request::new(&format!("{}{}", RPC_PROTOCOL_URL, token));
+    assert_eq!(
+      other.respond(&get, &acl).unwrap().status(),
+      StatusCode::NOT_FOUND
+    );
+    let response = store.respond(&get, &acl).unwrap();
+    assert_eq!(response.body().as_bytes(), Some(&[1, 2, 3][..]));
+    assert_eq!(
+      store.respond(&get, &acl).unwrap().status(),
+      StatusCode::NOT_FOUND
+    );
+
+    let token = "0123456789abcdef0123456789abcdef";
+    let post = Request::new(&format!("{}{}?id=abc-1", RPC_PROTOCOL_URL, token))
+      .with_method(Method::POST)
+      .with_body(vec![4, 5]);
+    assert_eq!(
+      store.respond(&post, &acl).unwrap().status(),
+      StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+      other.respond(&post, &acl).unwrap().status(),
+      StatusCode::CONFLICT
+    );
+    // The uploaded bytes only deserialize once resolved by the store of their webview
+    let params = json!([{ "__wry_bytes_ref__": token }]);
+    assert!(serde_json::from_value::<(RpcBytes,)>(params.clone()).is_err());
+    let mut resolved = params.clone();
+    other.resolve(&mut resolved);
+    assert_eq!(resolved, params);
+    store.resolve(&mut resolved);
+    assert!(!BytesPool::lock().entries.contains_key(token));
+    let (bytes,): (RpcBytes,) = serde_json::from_value(resolved).unwrap();
+    assert_eq!(bytes, RpcBytes(vec![4, 5]));
+
+    // The bytes of cancelled calls and of previous pages are dropped
+    store.respond(&post, &acl).unwrap();
+    store.release(&json!("abc-1"));
+    assert!(!BytesPool::lock().entries.contains_key(token));
+    store.respond(&post, &acl).unwrap();
+    other.clear();
+    assert!(BytesPool::lock().entries.contains_key(token));
+    store.clear();
+    assert!(!BytesPool::lock().entries.contains_key(token));
+
+    // Only the origins allowed to make RPC calls exchange bytes, with their own origin
+    let mut acl = RpcAcl::default();
+    acl.allow("https://example.com", vec!["add".to_string()]);
+    let post = |origin| {
+      Request::new(&format!("{}{}", RPC_PROTOCOL_URL, token))
+        .with_method(Method::POST)
+        .with_header(header::ORIGIN, HeaderValue::from_static(origin))
+    };
+    assert_eq!(
+      store
+        .respond(&post("https://evil.org"), &acl)
+        .unwrap()
+        .status(),
+      StatusCode::FORBIDDEN
+    );
+    let response = store.respond(&post("https://example.com"), &acl).unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+      response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+      "https://example.com"
+    );
+    store.clear();
+  }
+
+  #[test]
+  fn should_stop_cancelled_stream() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let dispatcher = Dispatcher {
+      tx,
+      js_calls: JsCalls::default(),
+      bytes: RpcBytesStore::default(),
+      codec: None,
+    };
+    let cancellations = Cancellations::default();
+    let id = Value::from("abc-1");
+    let sink = RpcSink::new(RpcResponder::new(
+      id.clone(),
+      dispatcher,
+      cancellations.register(&id),
+    ));
+
+    sink.send(1).unwrap();
+    assert_eq!(
+      rx.try_recv().unwrap(),
+      r#"window.external.rpc._next("abc-1", 1)"#
+    );
+
+    cancellations.cancel(&id);
+    assert!(sink.is_cancelled());
+    assert!(sink.send(2).is_err());
+    drop(sink);
+    assert!(rx.try_recv().is_err());
+  }
+
+  #[test]
+  fn should_settle_js_calls() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let calls = JsCalls::default();
+    let bytes = RpcBytesStore::default();
+    let code = move |result: Result<Value>| match result {
+      Err(Error::RpcError(e)) => e.code,
+      _ => 0,
+    };
+
+    let sender = tx.clone();
+    calls
+      .call(None, &bytes, "f", (), move |r| {
+        sender.send(code(r)).unwrap()
+      })
+      .unwrap()
+      .unwrap();
+    calls.navigated(None);
+    assert_eq!(rx.try_recv(), Ok(RpcError::CANCELLED));
+
+    calls.set_timeout(Some(Duration::from_millis(10)));
+    let sender = tx.clone();
+    calls
+      .call(None, &bytes, "f", (), move |r| {
+        sender.send(code(r)).unwrap()
+      })
+      .unwrap()
+      .unwrap();
+    assert_eq!(
+      rx.recv_timeout(Duration::from_secs(5)),
+      Ok(RpcError::TIMEOUT)
+    );
+
+    calls.close("closed", None);
+    let script = calls
+      .call(None, &bytes, "f", (), move |r| tx.send(code(r)).unwrap())
+      .unwrap();
+    assert!(script.is_none());
+    assert_eq!(rx.try_recv(), Ok(RpcError::CANCELLED));
+
+    // The panics of the callbacks are caught
+    let calls = JsCalls::default();
+    calls
+      .call(None, &bytes, "f", (), |_| panic!("callback"))
+      .unwrap()
+      .unwrap();
+    calls.navigated(None);
+  }
+
+  #[test]
+  fn should_check_origin_access() {
+    assert_eq!(
+      origin_of("https://example.com:443/index.html?a=b").as_deref(),
+      Some("https://example.com")
+    );
+    assert_eq!(
+      origin_of("http://localhost:8080/").as_deref(),
+      Some("http://localhost:8080")
+    );
+    assert_eq!(origin_of("data:text/html,<p>hi</p>"), None);
+    // The custom protocols give the same origin on every platform
+    assert_eq!(
+      origin_of("app:///index.html").as_deref(),
+      Some("app://localhost")
+    );
+    assert_eq!(
+      origin_of("app://localhost/index.html").as_deref(),
+      Some("app://localhost")
+    );
+    assert_eq!(origin_of("app://").as_deref(), Some("app://localhost"));
+
+    let mut acl = RpcAcl::default();
+    assert!(acl.is_allowed(None, "add"));
+
+    acl.allow("app://", vec!["*".to_string()]);
+    acl.allow("https://example.com/", vec!["add".to_string()]);
+    assert!(acl.is_allowed(Some("app://localhost"), "quit"));
+    assert!(acl.is_allowed(Some("https://example.com"), "add"));
+    assert!(!acl.is_allowed(Some("https://example.com"), "quit"));
+    assert!(!acl.is_allowed(Some("https://example.com.evil.org"), "add"));
+    assert!(!acl.is_allowed(Some("http://example.com"), "add"));
+    assert!(!acl.is_allowed(None, "add"));
+
+    assert!(acl.allows_origin(Some("https://example.com")));
+    assert!(!acl.allows_origin(Some("https://evil.org")));
+    assert!(!acl.allows_origin(None));
+  }
+
+  #[test]
+  fn should_escape_emitted_payload() {
+    let js = emit_script(
+      None,
+      &RpcBytesStore::default(),
+      "progress",
+      "</script>\u{2028}\"done\"",
+    )
+    .unwrap();
+    assert_eq!(
+      js,
+      r#"window.external.rpc._emit("progress", "</script>\u2028\"done\"")"#
+    );
+  }
+
+  #[cfg(feature = "msgpack")]
+  #[test]
+  fn should_exchange_packed_messages() {
+    let codec = MessagePackCodec;
+    let acl = RpcAcl::default();
+    let store = RpcBytesStore::default();
+    let request = json!({
+      "jsonrpc": "2.0",
+      "id": 1,
+      "method": "add",
+      "params": [1, -2, 1.5, "three"],
+    });
+    let token = "0123456789abcdef0123456789abcdee";
+    let post = Request::new(&format!("{}{}", RPC_PROTOCOL_URL, token))
+      .with_method(Method::POST)
+      .with_body(codec.encode(&request).unwrap());
+    store.respond(&post, &acl).unwrap();
+    let message = format!("{}{}", PACKED_PREFIX, token);
+    assert!(
+      unpack_message(Some(&codec), &RpcBytesStore::default(), &message)
+        .unwrap()
+        .is_err()
+    );
+    assert_eq!(
+      unpack_message(Some(&codec), &store, &message)
+        .unwrap()
+        .unwrap(),
+      request
+    );
+    assert!(unpack_message(Some(&codec), &store, &request.to_string()).is_none());
+
+    let js = emit_script(Some(&codec), &store, "progress", 42).unwrap();
+    let token: String = serde_json::from_str(
+      js.strip_prefix("window.external.rpc._receive(")
+        .and_then(|js| js.strip_suffix(')'))
+        .unwrap(),
+    )
+    .unwrap();
+    let response = store
+      .respond(
+        &Request::new(&format!("{}{}", RPC_PROTOCOL_URL, token)),
+        &RpcAcl::default(),
+      )
+      .unwrap();
+    assert_eq!(
+      codec.decode(response.body().as_bytes().unwrap()).unwrap(),
+      json!(["_emit", "progress", 42])
+    );
+  }
+}
@@ -0,0 +1,584 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+  fmt::Write,
+  rc::Rc,
+  sync::Arc,
+};
+
+use serde_json::Value;
+
+use super::RpcBytes;
+
+/// A Rust type with a TypeScript counterpart, used to generate the bindings of the methods
+/// registered with [`RpcRouter::with_typed_method`](crate::webview::RpcRouter::with_typed_method).
+///
+/// This is implemented for the primitive types and the common containers. With the `derive`
+/// feature, enabled by default, derive it for your own types next to `Serialize`: the derived type
+/// follows the `serde` attributes changing the JSON representation.
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # fn main() {
+/// use serde::{Deserialize, Serialize};
+/// use wry::webview::TsType;
+///
+/// #[derive(Serialize, Deserialize, TsType)]
+/// #[serde(rename_all = "camelCase")]
+/// struct User {
+///   user_name: String,
+///   age: Option<u32>,
+/// }
+///
+/// assert_eq!(User::ts_type(), "{ userName: string; age: number | null }");
+/// # }
+/// # #[cfg(not(feature = "derive"))]
+/// # fn main() {}
+/// ```
+///
+/// Implement it by hand for the types with a custom `Serialize` implementation, returning the
+/// TypeScript type of what they serialize to.
+pub trait TsType {
+  /// The TypeScript type of the JSON value this type serializes to.
+  fn ts_type() -> String;
+
+  /// The TypeScript types of the arguments of `window.rpc.call` when this type is the parameter
+  /// of a method. Javascript sends the arguments as an array, so tuples expand to one argument per
+  /// item while any other type is a single argument.
+  fn ts_params() -> Vec<String> {
+    vec![Self::ts_type()]
+  }
+}
+
+macro_rules! impl_ts_type {
+  ($ts:expr => $($ty:ty),*) => {
+    $(
+      impl TsType for $ty {
+        fn ts_type() -> String {
+          $ts.into()
+        }
+      }
+    )*
+  };
+}
+
+impl_ts_type!("boolean" => bool);
+impl_ts_type!("number" => i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+impl_ts_type!("string" => char, str, String);
+impl_ts_type!("unknown" => Value);
+impl_ts_type!("Uint8Array" => RpcBytes);
+
+impl TsType for () {
+  fn ts_type() -> String {
+    "null".into()
+  }
+
+  fn ts_params() -> Vec<String> {
+    vec![]
+  }
+}
+
+impl<T: TsType> TsType for Option<T> {
+  fn ts_type() -> String {
+    format!("{} | null", T::ts_type())
+  }
+}
+
+macro_rules! impl_ts_type_wrapper {
+  ($($ty:ident),*) => {
+    $(
+      impl<T: TsType + ?Sized> TsType for $ty<T> {
+        fn ts_type() -> String {
+          T::ts_type()
+        }
+
+        fn ts_params() -> Vec<String> {
+          T::ts_params()
+        }
+      }
+    )*
+  };
+}
+
+impl_ts_type_wrapper!(Box, Rc, Arc);
+
+macro_rules! impl_ts_type_array {
+  ($($ty:ident),*) => {
+    $(
+      impl<T: TsType> TsType for $ty<T> {
+        fn ts_type() -> String {
+          format!("Array<{}>", T::ts_type())
+        }
+      }
+    )*
+  };
+}
+
+impl_ts_type_array!(Vec, VecDeque, HashSet, BTreeSet);
+
+impl<T: TsType> TsType for [T] {
+  fn ts_type() -> String {
+    format!("Array<{}>", T::ts_type())
+  }
+}
+
+// JSON object keys are always strings, whatever the type of the map keys.
+impl<K, V: TsType> TsType for HashMap<K, V> {
+  fn ts_type() -> String {
+    format!("Record<string, {}>", V::ts_type())
+  }
+}
+
+impl<K, V: TsType> TsType for BTreeMap<K, V> {
+  fn ts_type() -> String {
+    format!("Record<string, {}>", V::ts_type())
+  }
+}
+
+macro_rules! impl_ts_type_tuple {
+  ($($name:ident),+) => {
+    impl<$($name: TsType),+> TsType for ($($name,)+) {
+      fn ts_type() -> String {
+        format!("[{}]", Self::ts_params().join(", "))
+      }
+
+      fn ts_params() -> Vec<String> {
+        vec![$($name::ts_type()),+]
+      }
+    }
+  };
+}
+
+impl_ts_type_tuple!(A);
+impl_ts_type_tuple!(A, B);
+impl_ts_type_tuple!(A, B, C);
+impl_ts_type_tuple!(A, B, C, D);
+impl_ts_type_tuple!(A, B, C, D, E);
+impl_ts_type_tuple!(A, B, C, D, E, F);
+impl_ts_type_tuple!(A, B, C, D, E, F, G);
+impl_ts_type_tuple!(A, B, C, D, E, F, G, H);
+
+/// The TypeScript signature of a method registered on a router.
+pub(crate) enum TsSignature {
+  Method { params: Vec<String>, result: String },
+  Stream { params: Vec<String>, item: String },
+  Untyped { stream: bool },
+}
+
+const HEADER: &str = "// Generated by wry from the methods of an `RpcRouter`, do not edit.\n";
+
+// Write the interfaces describing the methods and streams, as `name: (args) => result` members
+// where the result of a stream is the type of its items.
+fn write_interfaces(out: &mut String, signatures: &BTreeMap<String, TsSignature>) {
+  for (interface, streams) in &[("RpcMethods", false), ("RpcStreams", true)] {
+    if *streams {
+      out.push('\n');
+    }
+    let _ = writeln!(out, "export interface {} {{", interface);
+    for (name, signature) in signatures {
+      let member = match signature {
+        TsSignature::Method { params, result } if !streams => {
+          format!("({}) => {}", args(params), result)
+        }
+        TsSignature::Stream { params, item } if *streams => {
+          format!("({}) => {}", args(params), item)
+        }
+        TsSignature::Untyped { stream } if stream == streams => {
+          "(...params: any[]) => unknown".into()
+        }
+        _ => continue,
+      };
+      let _ = writeln!(out, "  {}: {};", quote(name), member);
+    }
+    out.push_str("}\n");
+  }
+}
+
+/// Get the declarations of the methods and of a `window.rpc` typed with them.
+pub(crate) fn definitions(signatures: &BTreeMap<String, TsSignature>) -> String {
+  let mut out = HEADER.to_string();
+  out.push('\n');
+  write_interfaces(&mut out, signatures);
+  out.push_str(
+    r#"
+export interface RpcClient {
+  call<M extends keyof RpcMethods>(
+    method: M,
+    ...params: Parameters<RpcMethods[M]>
+  ): Promise<ReturnType<RpcMethods[M]>>;
+  callWithOptions<M extends keyof RpcMethods>(
+    method: M,
+    params: Parameters<RpcMethods[M]>,
+    options?: { timeout?: number; signal?: AbortSignal }
+  ): Promise<ReturnType<RpcMethods[M]>>;
+  notify<M extends keyof RpcMethods>(
+    method: M,
+    ...params: Parameters<RpcMethods[M]>
+  ): Promise<void>;
+  stream<M extends keyof RpcStreams>(
+    method: M,
+    ...params: Parameters<RpcStreams[M]>
+  ): AsyncIterableIterator<ReturnType<RpcStreams[M]>>;
+  batch(
+    entries: Array<{ method: keyof RpcMethods; params?: unknown[]; notify?: boolean }>
+  ): Promise<PromiseSettledResult<unknown>[]>;
+  register(name: string, fn: (...params: any[]) => unknown): void;
+  unregister(name: string): void;
+  on(event: string, listener: (payload: any) => void): () => void;
+  off(event: string, listener?: (payload: any) => void): void;
+  timeout: number;
+}
+
+declare global {
+  interface Window {
+    rpc: RpcClient;
+  }
+}
+"#,
+  );
+  out
+}
+
+/// Get a module exporting a function calling each method.
+pub(crate) fn client(signatures: &BTreeMap<String, TsSignature>) -> String {
+  let mut out = HEADER.to_string();
+  out.push('\n');
+  write_interfaces(&mut out, signatures);
+  out.push_str("\nconst rpc = (window as any).rpc;\n");
+  let mut functions = HashSet::new();
+  for (name, signature) in signatures {
+    let function = function_name(name, &mut functions);
+    let (params, call_args, result) = match signature {
+      TsSignature::Method { params, result } => (
+        args(params),
+        arg_names(params),
+        format!("Promise<{}>", result),
+      ),
+      TsSignature::Stream { params, item } => (
+        args(params),
+        arg_names(params),
+        format!("AsyncIterableIterator<{}>", item),
+      ),
+      TsSignature::Untyped { stream: false } => (
+        "...params: any[]".into(),
+        ", ...params".into(),
+        "Promise<unknown>".into(),
+      ),
+      TsSignature::Untyped { stream: true } => (
+        "...params: any[]".into(),
+        ", ...params".into(),
+        "AsyncIterableIterator<unknown>".into(),
+      ),
+    };
+    let call = match signature {
+      TsSignature::Stream { .. } | TsSignature::Untyped { stream: true } => "stream",
+      _ => "call",
+    };
+    let _ = write!(
+      out,
+      "\nexport function {}({}): {} {{\n  return rpc.{}({}{});\n}}\n",
+      function,
+      params,
+      result,
+      call,
+      quote(name),
+      call_args
+    );
+  }
+  out
+}
+
+// A method name as a string literal.
+fn quote(name: &str) -> String {
+  serde_json::to_string(name).unwrap_or_default()
+}
+
+// Named arguments of a signature, like `arg0: number, arg1: string`.
+fn args(params: &[String]) -> String {
+  params
+    .iter()
+    .enumerate()
+    .map(|(i, ty)| format!("arg{}: {}", i, ty))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+// The arguments passed on by a function, like `, arg0, arg1`.
+fn arg_names(params: &[String]) -> String {
+  (0..params.len()).map(|i| format!(", arg{}", i)).collect()
+}
+
+// Words which can't name a function, including the `rpc` constant of the client module.
+const RESERVED: &[&str] = &[
+  "await",
+  "break",
+  "case",
+  "catch",
+  "class",
+  "const",
+  "continue",
+  "debugger",
+  "default",
+  "delete",
+  "do",
+  "else",
+  "enum",
+  "export",
+  "extends",
+  "false",
+  "finally",
+  "for",
+  "function",
+  "if",
+  "implements",
+  "import",
+  "in",
+  "instanceof",
+  "interface",
+  "let",
+  "new",
+  "null",
+  "package",
+  "private",
+  "protected",
+  "public",
+  "return",
+  "rpc",
+  "static",
+  "super",
+  "switch",
+  "this",
+  "throw",
+  "true",
+  "try",
+  "typeof",
+  "var",
+  "void",
+  "while",
+  "with",
+  "yield",
+];
+
+// Get the name of the client function of a method, unique among the names already taken.
+//
+// Method names turning into the same identifier, like `a-b` and `a.b`, get numbered. Names
+// without any valid character become `method`, and reserved words get a `_` suffix.
+fn function_name(name: &str, taken: &mut HashSet<String>) -> String {
+  let mut ident = camel_case(name);
+  if ident.is_empty() {
+    ident.push_str("method");
+  }
+  if RESERVED.contains(&ident.as_str()) {
+    ident.push('_');
+  }
+  let mut function = ident.clone();
+  let mut n = 2;
+  while taken.contains(&function) {
+    function = format!("{}{}", ident, n);
+    n += 1;
+  }
+  taken.insert(function.clone());
+  function
+}
+
+// Turn a method name like `send-parameters` into a valid identifier like `sendParameters`.
+fn camel_case(name: &str) -> String {
+  let mut ident = String::new();
+  let mut upper = false;
+  for c in name.chars() {
+    if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+      if ident.is_empty() && c.is_ascii_digit() {
+        ident.push('_');
+      }
+      if upper && !ident.is_empty() {
+        ident.push(c.to_ascii_uppercase());
+      } else {
+        ident.push(c);
+      }
+      upper = false;
+    } else {
+      upper = true;
+    }
+  }
+  ident
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::webview::RpcRouter;
+
+  // The types deriving `TsType`, which needs the `derive` feature
+  #[cfg(feature = "derive")]
+  mod derive {
+    use super::*;
+    use crate::webview::TsType;
+
+    #[derive(TsType)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct User {
+      user_name: String,
+      #[serde(rename = "user-age")]
+      age: Option<u32>,
+      #[serde(default)]
+      tags: Vec<String>,
+      #[serde(skip)]
+      password: String,
+      #[serde(flatten)]
+      extra: HashMap<String, Value>,
+    }
+
+    #[derive(TsType)]
+    #[allow(dead_code)]
+    enum External {
+      Unit,
+      Newtype(u8),
+      Tuple(u8, String),
+      Struct { a: bool },
+    }
+
+    #[derive(TsType)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    #[allow(dead_code)]
+    enum Internal {
+      UnitVariant,
+      Struct { a: bool },
+    }
+
+    #[derive(TsType)]
+    #[serde(tag = "t", content = "c")]
+    #[allow(dead_code)]
+    enum Adjacent {
+      Unit,
+      Newtype(u8),
+    }
+
+    #[derive(TsType)]
+    #[serde(untagged)]
+    #[allow(dead_code)]
+    enum Untagged {
+      Unit,
+      Newtype(u8),
+    }
+
+    #[derive(TsType)]
+    #[allow(dead_code)]
+    struct Page<T> {
+      items: Vec<T>,
+      total: usize,
+    }
+
+    #[derive(TsType)]
+    #[allow(dead_code)]
+    struct Newtype(String);
+
+    #[derive(TsType)]
+    #[allow(dead_code)]
+    struct Tuple(u8, bool);
+
+    #[derive(TsType)]
+    struct Unit;
+
+    #[derive(TsType)]
+    #[serde(transparent)]
+    #[allow(dead_code)]
+    struct Transparent {
+      inner: u8,
+    }
+
+    #[test]
+    fn should_derive_ts_type() {
+      assert_eq!(
+        User::ts_type(),
+        "{ userName: string; \"user-age\": number | null; tags?: Array<string> } & Record<string, unknown>"
+      );
+      assert_eq!(
+        External::ts_type(),
+        "\"Unit\" | { Newtype: number } | { Tuple: [number, string] } | { Struct: { a: boolean } }"
+      );
+      assert_eq!(
+        Internal::ts_type(),
+        "{ type: \"unit_variant\" } | { type: \"struct\"; a: boolean }"
+      );
+      assert_eq!(
+        Adjacent::ts_type(),
+        "{ t: \"Unit\" } | { t: \"Newtype\"; c: number }"
+      );
+      assert_eq!(Untagged::ts_type(), "null | number");
+      assert_eq!(
+        Page::<bool>::ts_type(),
+        "{ items: Array<boolean>; total: number }"
+      );
+      assert_eq!(Newtype::ts_type(), "string");
+      assert_eq!(Tuple::ts_type(), "[number, boolean]");
+      assert_eq!(Unit::ts_type(), "null");
+      assert_eq!(Transparent::ts_type(), "number");
+    }
+  }
+
+  fn router() -> RpcRouter {
+    RpcRouter::new()
+      .with_typed_method("add", |_, (a, b): (i64, i64)| Ok(a + b))
+      .with_typed_stream_method("count", |_, (to,): (u32,), sink| {
+        for i in 0..to {
+          sink.send(i)?;
+        }
+        Ok(())
+      })
+      .with_method("untyped", |_, _: Value| Ok(()))
+  }
+
+  #[test]
+  fn should_write_typescript_definitions() {
+    let definitions = router().typescript_definitions();
+    assert!(definitions.contains(
+      "export interface RpcMethods {\n  \"add\": (arg0: number, arg1: number) => number;\n  \"untyped\": (...params: any[]) => unknown;\n}\n"
+    ));
+    assert!(definitions
+      .contains("export interface RpcStreams {\n  \"count\": (arg0: number) => number;\n}\n"));
+    assert!(definitions.contains("rpc: RpcClient;"));
+  }
+
+  #[test]
+  fn should_write_typescript_client() {
+    let client = router().typescript_client();
+    assert!(client.contains(
+      "export function add(arg0: number, arg1: number): Promise<number> {\n  return rpc.call(\"add\", arg0, arg1);\n}\n"
+    ));
+    assert!(client.contains(
+      "export function count(arg0: number): AsyncIterableIterator<number> {\n  return rpc.stream(\"count\", arg0);\n}\n"
+    ));
+    assert!(client.contains(
+      "export function untyped(...params: any[]): Promise<unknown> {\n  return rpc.call(\"untyped\", ...params);\n}\n"
+    ));
+  }
+
+  #[test]
+  fn should_name_client_functions_uniquely() {
+    let client = ["a-b", "a.b", "aB", "", "--", "delete", "rpc", "2fa"]
+      .iter()
+      .fold(RpcRouter::new(), |router, name| {
+        router.with_typed_method(name, |_, (): ()| Ok(()))
+      })
+      .typescript_client();
+    for (function, method) in &[
+      ("_2fa", "2fa"),
+      ("method", ""),
+      ("method2", "--"),
+      ("aB", "a-b"),
+      ("aB2", "a.b"),
+      ("aB3", "aB"),
+      ("delete_", "delete"),
+      ("rpc_", "rpc"),
+    ] {
+      assert!(client.contains(&format!(
+        "export function {}(): Promise<null> {{\n  return rpc.call({:?});",
+        function, method
+      )));
+    }
+  }
+}
@@ -5,13 +5,14 @@
 mod file_drop;
 
 use crate::{
-  webview::{mimetype::MimeType, FileDropEvent, RpcRequest, RpcResponse},
-  Result,
+  http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode},
+  webview::{rpc::RpcHandler, ErrorHandler, FileDropEvent, Interception, ProtocolOptions},
+  Error, Result,
 };
 
 use file_drop::FileDropController;
 
-use std::{collections::HashSet, os::raw::c_void, path::PathBuf, rc::Rc};
+use std::{collections::HashSet, io::Read, os::raw::c_void, path::PathBuf, rc::Rc};
 
 use once_cell::unsync::OnceCell;
 use url::Url;
@@ -39,11 +40,14 @@ impl InnerWebView {
     _transparent: bool,
     custom_protocols: Vec<(
       String,
-      Box<dyn Fn(&Window, &str) -> Result<Vec<u8>> + 'static>,
+      ProtocolOptions,
+      Box<dyn Fn(&Window, &Request) -> Response + 'static>,
     )>,
-    rpc_handler: Option<Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>>,
+    request_interceptor: Option<Box<dyn Fn(&Window, &Request) -> Interception>>,
+    rpc_handler: Option<RpcHandler>,
     file_drop_handler: Option<Box<dyn Fn(&Window, FileDropEvent) -> bool>>,
     data_directory: Option<PathBuf>,
+    error_handler: Option<ErrorHandler>,
   ) -> Result<Self> {
     let hwnd = window.hwnd() as HWND;
 
@@ -96,65 +100,118 @@ impl InnerWebView {
           w.add_script_to_execute_on_document_created(&js, |_| (Ok(())))?;
         }
 
+        let custom_protocol_names: HashSet<String> = custom_protocols
+          .iter()
+          .map(|(name, ..)| name.clone())
+          .collect();
+
+        // The `call_js` calls and the RPC bytes of a page are gone with it
+        if let Some(rpc_handler) = rpc_handler.as_ref() {
+          let dispatcher = rpc_handler.dispatcher.clone();
+          let panic_hook = rpc_handler.panic_hook.clone();
+          w.add_content_loading(move |_, _| {
+            dispatcher.navigated(panic_hook.as_ref());
+            Ok(())
+          })?;
+        }
+
         // Message handler
         let window_ = window.clone();
+        let protocol_names = custom_protocol_names.clone();
+        let handler = error_handler.clone();
         w.add_web_message_received(move |webview, args| {
           let js = args.try_get_web_message_as_string()?;
           if let Some(rpc_handler) = rpc_handler.as_ref() {
-            match super::rpc_proxy(&window_, js, rpc_handler) {
+            let source = super::unmap_protocol_uri(args.get_source()?, &protocol_names);
+            match super::rpc_proxy(&window_, js, Some(&source), rpc_handler) {
               Ok(result) => {
                 if let Some(ref script) = result {
-                  webview.execute_script(script, |_| (Ok(())))?;
+                  if let Err(e) = webview.execute_script(script, |_| (Ok(()))) {
+                    super::report_error(handler.as_ref(), &window_, e.into());
+                  }
                 }
               }
-              Err(e) => {
-                eprintln!("{}", e);
-              }
+              Err(e) => super::report_error(handler.as_ref(), &window_, e),
             }
           }
           Ok(())
         })?;
 
-        let mut custom_protocol_names = HashSet::new();
-        for (name, function) in custom_protocols {
+        for (name, _, function) in custom_protocols {
           // WebView2 doesn't support non-standard protocols yet, so we have to use this workaround
           // See https://github.com/MicrosoftEdge/WebView2Feedback/issues/73
-          custom_protocol_names.insert(name.clone());
           w.add_web_resource_requested_filter(
             &format!("https://custom-protocol-{}*", name),
             webview2::WebResourceContext::All,
           )?;
           let env_clone = env_.clone();
           let window_ = window.clone();
+          let protocol_names = custom_protocol_names.clone();
+          let handler = error_handler.clone();
           w.add_web_resource_requested(move |_, args| {
-            let uri = args.get_request()?.get_uri()?;
-            // Undo the protocol workaround when giving path to resolver
-            let path = &uri.replace(
-              &format!("https://custom-protocol-{}", name),
-              &format!("{}://", name),
-            );
+            let request = args.get_request()?;
+            let uri = request.get_uri()?;
+            // The handlers are called for the requests of every filter
+            let path = match super::strip_protocol_prefix(&uri, &name) {
+              // Undo the protocol workaround when giving path to resolver
+              Some(rest) => format!("{}://{}", name, rest),
+              None => return Ok(()),
+            };
 
-            match function(&window_, path) {
-              Ok(content) => {
-                let mime = MimeType::parse(&content, &uri);
-                let stream = webview2::Stream::from_bytes(&content);
-                let response = env_clone.create_web_resource_response(
-                  stream,
-                  200,
-                  "OK",
-                  &format!("Content-Type: {}", mime),
-                )?;
-                args.put_response(response)?;
-                Ok(())
-              }
-              Err(_) => Err(webview2::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Error loading requested file",
-              ))),
+            let response = function(&window_, &get_request(&request, &path, &protocol_names)?);
+            if let Err(e) = put_response(&env_clone, &args, response, &path) {
+              super::report_error(handler.as_ref(), &window_, e);
+            }
+            Ok(())
+          })?;
+        }
+
+        // Request interception
+        if let Some(interceptor) = request_interceptor {
+          w.add_web_resource_requested_filter("*", webview2::WebResourceContext::All)?;
+          let env_clone = env_.clone();
+          let window_ = window.clone();
+          let protocol_names = custom_protocol_names.clone();
+          let handler = error_handler.clone();
+          w.add_web_resource_requested(move |_, args| {
+            let request = args.get_request()?;
+            let uri = request.get_uri()?;
+            // Leave the custom protocols, which use the https scheme, to their handlers
+            if !(uri.starts_with("http://") || uri.starts_with("https://"))
+              || uri.starts_with("https://custom-protocol-")
+            {
+              return Ok(());
+            }
+
+            let response =
+              match interceptor(&window_, &get_request(&request, &uri, &protocol_names)?) {
+                Interception::Allow => return Ok(()),
+                Interception::Redirect(url) => return request.put_uri(&url),
+                // WebView2 can't fail a request, so it's answered with a forbidden status
+                Interception::Block => Response::new(Vec::new()).with_status(StatusCode::FORBIDDEN),
+                Interception::Respond(response) => response,
+              };
+            if let Err(e) = put_response(&env_clone, &args, response, &uri) {
+              super::report_error(handler.as_ref(), &window_, e);
             }
+            Ok(())
           })?;
         }
 
+        // Navigation errors
+        let window_ = window.clone();
+        let handler = error_handler.clone();
+        w.add_navigation_completed(move |webview, args| {
+          if !args.get_is_success()? {
+            let error = Error::NavigationError(
+              webview.get_source()?,
+              format!("{:?}", args.get_web_error_status()?),
+            );
+            super::report_error(handler.as_ref(), &window_, error);
+          }
+          Ok(())
+        })?;
+
         // Enable clipboard
         w.add_permission_requested(|_, args| {
           let kind = args.get_permission_kind()?;
@@ -230,3 +287,48 @@ impl InnerWebView {
     Ok(())
   }
 }
+
+// Answer the request of WebView2 with a [`Response`].
+fn put_response(
+  env: &webview2::Environment,
+  args: &webview2::WebResourceRequestedEventArgs,
+  response: Response,
+  uri: &str,
+) -> Result<()> {
+  let (status, reason) = (response.status().as_u16() as i32, response.reason());
+  let headers = response.raw_headers(uri);
+  let body = response.into_body().into_bytes()?;
+  let stream = webview2::Stream::from_bytes(&body);
+  let response = env.create_web_resource_response(stream, status, reason, &headers)?;
+  args.put_response(response)?;
+  Ok(())
+}
+
+// Get the [`Request`] of the page from the request of WebView2, with the origin of the pages of
+// the custom protocols undoing the protocol workaround.
+fn get_request(
+  request: &webview2::WebResourceRequest,
+  uri: &str,
+  protocol_names: &HashSet<String>,
+) -> webview2::Result<Request> {
+  let method = request.get_method()?;
+  let mut req =
+    Request::new(uri).with_method(Method::from_bytes(method.as_bytes()).unwrap_or(Method::GET));
+  for (name, mut value) in request.get_headers()?.get_iterator()? {
+    if name.eq_ignore_ascii_case("origin") {
+      value = super::unmap_protocol_uri(value, protocol_names);
+    }
+    if let (Ok(name), Ok(value)) = (
+      HeaderName::from_bytes(name.as_bytes()),
+      HeaderValue::from_str(&value),
+    ) {
+      req = req.with_header(name, value);
+    }
+  }
+  if let Some(mut content) = request.get_content()? {
+    let mut body = Vec::new();
+    content.read_to_end(&mut body)?;
+    req = req.with_body(body);
+  }
+  Ok(req)
+}
@@ -17,8 +17,9 @@ use windows_webview2::{
 };
 
 use crate::{
-  webview::{mimetype::MimeType, FileDropEvent, RpcRequest, RpcResponse},
-  Result,
+  http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode},
+  webview::{rpc::RpcHandler, ErrorHandler, FileDropEvent, Interception, ProtocolOptions},
+  Error, Result,
 };
 
 use file_drop::FileDropController;
@@ -59,11 +60,14 @@ impl InnerWebView {
     #[allow(unused_variables)] transparent: bool,
     custom_protocols: Vec<(
       String,
-      Box<dyn Fn(&Window, &str) -> Result<Vec<u8>> + 'static>,
+      ProtocolOptions,
+      Box<dyn Fn(&Window, &Request) -> Response + 'static>,
     )>,
-    rpc_handler: Option<Box<dyn Fn(&Window, RpcRequest) -> Option<RpcResponse>>>,
+    request_interceptor: Option<Box<dyn Fn(&Window, &Request) -> Interception>>,
+    rpc_handler: Option<RpcHandler>,
     file_drop_handler: Option<Box<dyn Fn(&Window, FileDropEvent) -> bool>>,
     data_directory: Option<PathBuf>,
+    error_handler: Option<ErrorHandler>,
   ) -> Result<Self> {
     let hwnd = HWND(window.hwnd() as _);
 
@@ -114,8 +118,28 @@ impl InnerWebView {
       wait_for_async_operation(w.AddScriptToExecuteOnDocumentCreatedAsync(js.as_str())?)?;
     }
 
+    let custom_protocol_names: HashSet<String> = custom_protocols
+      .iter()
+      .map(|(name, ..)| name.clone())
+      .collect();
+
+    // The `call_js` calls and the RPC bytes of a page are gone with it
+    if let Some(rpc_handler) = rpc_handler.as_ref() {
+      let dispatcher = rpc_handler.dispatcher.clone();
+      let panic_hook = rpc_handler.panic_hook.clone();
+      w.ContentLoading(TypedEventHandler::<
+        webview2::CoreWebView2,
+        webview2::CoreWebView2ContentLoadingEventArgs,
+      >::new(move |_, _| {
+        dispatcher.navigated(panic_hook.as_ref());
+        Ok(())
+      }))?;
+    }
+
     // Message handler
     let window_ = window.clone();
+    let protocol_names = custom_protocol_names.clone();
+    let handler = error_handler.clone();
     w.WebMessageReceived(TypedEventHandler::<
       webview2::CoreWebView2,
       webview2::CoreWebView2WebMessageReceivedEventArgs,
@@ -125,58 +149,51 @@ impl InnerWebView {
           String::from_utf16(args.TryGetWebMessageAsString()?.as_wide()),
           rpc_handler.as_ref(),
         ) {
-          match super::rpc_proxy(&window_, js, rpc_handler) {
+          let source = String::from_utf16(args.Source()?.as_wide())
+            .ok()
+            .map(|source| super::unmap_protocol_uri(source, &protocol_names));
+          match super::rpc_proxy(&window_, js, source.as_deref(), rpc_handler) {
             Ok(result) => {
               if let Some(ref script) = result {
-                let _ = webview.ExecuteScriptAsync(script.as_str())?;
+                if let Err(e) = webview.ExecuteScriptAsync(script.as_str()) {
+                  super::report_error(handler.as_ref(), &window_, e.into());
+                }
               }
             }
-            Err(e) => {
-              eprintln!("{}", e);
-            }
+            Err(e) => super::report_error(handler.as_ref(), &window_, e),
           }
         }
       }
       Ok(())
     }))?;
 
-    let mut custom_protocol_names = HashSet::new();
-    for (name, function) in custom_protocols {
+    for (name, _, function) in custom_protocols {
       // WebView2 doesn't support non-standard protocols yet, so we have to use this workaround
       // See https://github.com/MicrosoftEdge/WebView2Feedback/issues/73
-      custom_protocol_names.insert(name.clone());
       w.AddWebResourceRequestedFilter(
         format!("https://custom-protocol-{}*", name).as_str(),
         webview2::CoreWebView2WebResourceContext::All,
       )?;
       let env_ = env.clone();
       let window_ = window.clone();
+      let protocol_names = custom_protocol_names.clone();
+      let handler = error_handler.clone();
 
       w.WebResourceRequested(TypedEventHandler::<
         webview2::CoreWebView2,
         webview2::CoreWebView2WebResourceRequestedEventArgs,
       >::new(move |_, args| {
         if let Some(args) = args {
-          if let Ok(uri) = String::from_utf16(args.Request()?.Uri()?.as_wide()) {
+          let request = args.Request()?;
+          // The handlers are called for the requests of every filter
+          let uri = String::from_utf16(request.Uri()?.as_wide()).unwrap_or_default();
+          if let Some(rest) = super::strip_protocol_prefix(&uri, &name) {
             // Undo the protocol workaround when giving path to resolver
-            let path = uri.replace(
-              &format!("https://custom-protocol-{}", name),
-              &format!("{}://", name),
-            );
-
-            if let Ok(content) = function(&window_, &path) {
-              let mime = MimeType::parse(&content, &uri);
-              let stream = InMemoryRandomAccessStream::new()?;
-              let writer = DataWriter::CreateDataWriter(stream.clone())?;
-              writer.WriteBytes(&content)?;
-              writer.DetachStream()?;
-              let response = env_.CreateWebResourceResponse(
-                stream,
-                200,
-                "OK",
-                format!("Content-Type: {}", mime).as_str(),
-              )?;
-              args.SetResponse(response)?;
+            let path = format!("{}://{}", name, rest);
+
+            let response = function(&window_, &get_request(&request, &path, &protocol_names)?);
+            if let Err(e) = set_response(&env_, &args, response, &path) {
+              super::report_error(handler.as_ref(), &window_, e);
             }
           }
         }
@@ -185,6 +202,65 @@ impl InnerWebView {
       }))?;
     }
 
+    // Request interception
+    if let Some(interceptor) = request_interceptor {
+      w.AddWebResourceRequestedFilter("*", webview2::CoreWebView2WebResourceContext::All)?;
+      let env_ = env.clone();
+      let window_ = window.clone();
+      let protocol_names = custom_protocol_names.clone();
+      let handler = error_handler.clone();
+
+      w.WebResourceRequested(TypedEventHandler::<
+        webview2::CoreWebView2,
+        webview2::CoreWebView2WebResourceRequestedEventArgs,
+      >::new(move |_, args| {
+        if let Some(args) = args {
+          let request = args.Request()?;
+          let uri = match String::from_utf16(request.Uri()?.as_wide()) {
+            Ok(uri) => uri,
+            Err(_) => return Ok(()),
+          };
+          // Leave the custom protocols, which use the https scheme, to their handlers
+          if !(uri.starts_with("http://") || uri.starts_with("https://"))
+            || uri.starts_with("https://custom-protocol-")
+          {
+            return Ok(());
+          }
+
+          let response = match interceptor(&window_, &get_request(&request, &uri, &protocol_names)?)
+          {
+            Interception::Allow => return Ok(()),
+            Interception::Redirect(url) => return request.SetUri(url.as_str()),
+            // WebView2 can't fail a request, so it's answered with a forbidden status
+            Interception::Block => Response::new(Vec::new()).with_status(StatusCode::FORBIDDEN),
+            Interception::Respond(response) => response,
+          };
+          if let Err(e) = set_response(&env_, &args, response, &uri) {
+            super::report_error(handler.as_ref(), &window_, e);
+          }
+        }
+
+        Ok(())
+      }))?;
+    }
+
+    // Navigation errors
+    let window_ = window.clone();
+    let handler = error_handler.clone();
+    w.NavigationCompleted(TypedEventHandler::<
+      webview2::CoreWebView2,
+      webview2::CoreWebView2NavigationCompletedEventArgs,
+    >::new(move |webview, args| {
+      if let (Some(webview), Some(args)) = (webview, args) {
+        if !args.IsSuccess()? {
+          let uri = String::from_utf16_lossy(webview.Source()?.as_wide());
+          let error = Error::NavigationError(uri, format!("{:?}", args.WebErrorStatus()?));
+          super::report_error(handler.as_ref(), &window_, error);
+        }
+      }
+      Ok(())
+    }))?;
+
     // Enable clipboard
     w.PermissionRequested(TypedEventHandler::<
       webview2::CoreWebView2,
@@ -266,6 +342,68 @@ impl InnerWebView {
   }
 }
 
+// Answer the request of WebView2 with a [`Response`].
+fn set_response(
+  env: &webview2::CoreWebView2Environment,
+  args: &webview2::CoreWebView2WebResourceRequestedEventArgs,
+  response: Response,
+  uri: &str,
+) -> Result<()> {
+  let (status, reason) = (response.status().as_u16() as i32, response.reason());
+  let headers = response.raw_headers(uri);
+  let body = response.into_body().into_bytes()?;
+  let stream = InMemoryRandomAccessStream::new()?;
+  let writer = DataWriter::CreateDataWriter(stream.clone())?;
+  writer.WriteBytes(&body)?;
+  writer.DetachStream()?;
+  let response = env.CreateWebResourceResponse(stream, status, reason, headers.as_str())?;
+  args.SetResponse(response)?;
+  Ok(())
+}
+
+// Get the [`Request`] of the page from the request of WebView2, with the origin of the pages of
+// the custom protocols undoing the protocol workaround.
+fn get_request(
+  request: &webview2::CoreWebView2WebResourceRequest,
+  uri: &str,
+  protocol_names: &HashSet<String>,
+) -> windows::Result<Request> {
+  let method = String::from_utf16_lossy(request.Method()?.as_wide());
+  let mut req =
+    Request::new(uri).with_method(Method::from_bytes(method.as_bytes()).unwrap_or(Method::GET));
+
+  let headers = request.Headers()?.First()?;
+  while headers.HasCurrent()? {
+    let header = headers.Current()?;
+    let name = String::from_utf16_lossy(header.Key()?.as_wide());
+    let mut value = String::from_utf16_lossy(header.Value()?.as_wide());
+    if name.eq_ignore_ascii_case("origin") {
+      value = super::unmap_protocol_uri(value, protocol_names);
+    }
+    if let (Ok(name), Ok(value)) = (
+      HeaderName::from_bytes(name.as_bytes()),
+      HeaderValue::from_str(&value),
+    ) {
+      req = req.with_header(name, value);
+    }
+    headers.MoveNext()?;
+  }
+
+  // The content is null when the request has no body
+  if let Ok(content) = request.Content() {
+    let size = content.Size().unwrap_or_default() as u32;
+    if size > 0 {
+      let reader = DataReader::CreateDataReader(content.GetInputStreamAt(0)?)?;
+      reader.LoadAsync(size)?.get()?;
+      let mut body = vec![0; size as usize];
+      reader.ReadBytes(&mut body)?;
+      req = req.with_body(body);
+    }
+  }
+
+  Ok(req)
+}
+
 /// The WebView2 threading model runs everything on the UI thread, including callbacks which it triggers
 /// with `PostMessage`, and we're using this here because it's waiting for some async operations in WebView2
 /// to finish before starting the main message loop in `EventLoop::run`. As long as there are no pending
@@ -0,0 +1,213 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Build script helpers of [wry](https://docs.rs/wry), without its platform dependencies so they
+//! stay cheap in the `[build-dependencies]`.
+//!
+//! [`EmbeddedAssetsBuilder`] embeds the files of a directory in the binary. In `build.rs`:
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> {
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! wry_build::EmbeddedAssetsBuilder::new("dist").write(format!("{}/assets.rs", out_dir))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! And in the application:
+//!
+//! ```ignore
+//! static ASSETS: EmbeddedAssets = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+//! ```
+//!
+//! The `embed-gzip` and `embed-brotli` features compress the embedded files. Enable the same
+//! features of `wry` to decompress them for the pages which don't accept their encoding: the
+//! compressed assets refer to `wry::webview::EmbeddedAsset::GZIP` and `BROTLI`, which don't exist
+//! without them.
+
+use std::{
+  fmt::Write as _,
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+/// Embed the files of a directory in the binary, to serve them with `wry::webview::EmbeddedAssets`.
+#[derive(Debug, Clone)]
+pub struct EmbeddedAssetsBuilder {
+  dir: PathBuf,
+  #[cfg(feature = "embed-gzip")]
+  gzip: bool,
+  #[cfg(feature = "embed-brotli")]
+  brotli: bool,
+}
+
+impl EmbeddedAssetsBuilder {
+  /// Create a builder embedding the files of the directory and its subdirectories.
+  pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+    Self {
+      dir: dir.into(),
+      #[cfg(feature = "embed-gzip")]
+      gzip: false,
+      #[cfg(feature = "embed-brotli")]
+      brotli: false,
+    }
+  }
+
+  /// Compress the files with gzip when it makes them smaller.
+  #[cfg(feature = "embed-gzip")]
+  pub fn with_gzip(mut self, gzip: bool) -> Self {
+    self.gzip = gzip;
+    self
+  }
+
+  /// Compress the files with brotli when it makes them smaller. With gzip too, the smallest of
+  /// both is embedded.
+  #[cfg(feature = "embed-brotli")]
+  pub fn with_brotli(mut self, brotli: bool) -> Self {
+    self.brotli = brotli;
+    self
+  }
+
+  /// Write the `EmbeddedAssets` to a file to `include!`, and the compressed files to a
+  /// directory named like it without its extension. Cargo runs the build script again when the
+  /// embedded directory changes.
+  pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let dir = self.dir.canonicalize()?;
+    let compressed_dir = path.with_extension("");
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut files = Vec::new();
+    collect_files(&dir, &mut files)?;
+    let mut assets = files
+      .into_iter()
+      .map(|file| {
+        let name = file
+          .strip_prefix(&dir)
+          .unwrap_or(&file)
+          .iter()
+          .map(|s| s.to_string_lossy())
+          .collect::<Vec<_>>()
+          .join("/");
+        (name, file)
+      })
+      .collect::<Vec<_>>();
+    assets.sort();
+
+    let mut source = String::from("wry::webview::EmbeddedAssets::new(&[\n");
+    for (i, (name, file)) in assets.into_iter().enumerate() {
+      let (file, encoding) = match self.compress(&fs::read(&file)?)? {
+        Some((compressed, encoding)) => {
+          fs::create_dir_all(&compressed_dir)?;
+          let compressed_file = compressed_dir.join(i.to_string());
+          fs::write(&compressed_file, compressed)?;
+          (
+            compressed_file,
+            format!("Some(wry::webview::EmbeddedAsset::{})", encoding),
+          )
+        }
+        None => (file, "None".into()),
+      };
+      let _ = writeln!(
+        source,
+        "  wry::webview::EmbeddedAsset {{ path: {:?}, bytes: include_bytes!({:?}), encoding: {} }},",
+        name, file, encoding
+      );
+    }
+    source.push_str("])\n");
+    fs::write(path, source)
+  }
+
+  // Compress the content of a file with the encoding making it the smallest, if any does, along
+  // with the name of the constant of the encoding in `wry`.
+  fn compress(&self, bytes: &[u8]) -> io::Result<Option<(Vec<u8>, &'static str)>> {
+    #[allow(unused_mut)]
+    let mut compressed: Vec<(Vec<u8>, &'static str)> = Vec::new();
+    #[cfg(feature = "embed-gzip")]
+    if self.gzip {
+      use std::io::Write;
+      let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+      encoder.write_all(bytes)?;
+      compressed.push((encoder.finish()?, "GZIP"));
+    }
+    #[cfg(feature = "embed-brotli")]
+    if self.brotli {
+      let mut output = Vec::new();
+      let params = brotli::enc::BrotliEncoderParams::default();
+      brotli::BrotliCompress(&mut &bytes[..], &mut output, &params)?;
+      compressed.push((output, "BROTLI"));
+    }
+    Ok(
+      compressed
+        .into_iter()
+        .filter(|(compressed, _)| compressed.len() < bytes.len())
+        .min_by_key(|(compressed, _)| compressed.len()),
+    )
+  }
+}
+
+// Collect the files of a directory and its subdirectories.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      collect_files(&path, files)?;
+    } else {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_write_embedded_assets() {
+    let dir = std::env::temp_dir().join(format!("wry-build-assets-{}", std::process::id()));
+    fs::create_dir_all(dir.join("dist").join("assets")).unwrap();
+    fs::write(dir.join("dist").join("index.html"), "<h1>index</h1>").unwrap();
+    fs::write(dir.join("dist").join("assets").join("main.js"), "main()").unwrap();
+
+    EmbeddedAssetsBuilder::new(dir.join("dist"))
+      .write(dir.join("assets.rs"))
+      .unwrap();
+    let source = fs::read_to_string(dir.join("assets.rs")).unwrap();
+    let lines = source.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "wry::webview::EmbeddedAssets::new(&[");
+    assert!(lines[1].contains("path: \"assets/main.js\"") && lines[1].contains("encoding: None"));
+    assert!(lines[2].contains("path: \"index.html\""));
+    assert_eq!(lines[3], "])");
+    assert!(!dir.join("assets").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[cfg(feature = "embed-gzip")]
+  #[test]
+  fn should_embed_compressed_assets() {
+    let dir = std::env::temp_dir().join(format!("wry-embedded-assets-{}", std::process::id()));
+    fs::create_dir_all(dir.join("dist")).unwrap();
+    let html = "<p>wry</p>".repeat(100);
+    fs::write(dir.join("dist").join("index.html"), &html).unwrap();
+    fs::write(dir.join("dist").join("a.txt"), "a").unwrap();
+
+    EmbeddedAssetsBuilder::new(dir.join("dist"))
+      .with_gzip(true)
+      .write(dir.join("assets.rs"))
+      .unwrap();
+    let source = fs::read_to_string(dir.join("assets.rs")).unwrap();
+    let lines = source.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].contains("path: \"a.txt\"") && lines[1].contains("encoding: None"));
+    assert!(
+      lines[2].contains("path: \"index.html\"")
+        && lines[2].contains("Some(wry::webview::EmbeddedAsset::GZIP)")
+    );
+    assert!(dir.join("assets").join("1").is_file());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,531 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Derive macros of [wry](https://docs.rs/wry), re-exported by its `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+  parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Lit, Meta,
+  NestedMeta, Result, Type,
+};
+
+/// Derive `wry::webview::TsType`, describing the JSON a type serializes to with `serde`.
+///
+/// The `serde` attributes changing the representation are followed: `rename`, `rename_all`,
+/// `skip`, `skip_serializing`, `default`, `skip_serializing_if`, `flatten`, `transparent`, and the
+/// `tag`, `content` and `untagged` representations of the enums. Every field type must implement
+/// `TsType` as well.
+///
+/// The types of the fields are written inline, so recursive types aren't supported.
+#[proc_macro_derive(TsType, attributes(serde))]
+pub fn derive_ts_type(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  match ts_type(input) {
+    Ok(tokens) => tokens.into(),
+    Err(e) => e.to_compile_error().into(),
+  }
+}
+
+fn ts_type(mut input: DeriveInput) -> Result<TokenStream2> {
+  let attrs = SerdeAttrs::parse(&input.attrs)?;
+  let parts = match &input.data {
+    Data::Struct(data) => struct_type(&input.ident.to_string(), &attrs, &data.fields)?,
+    Data::Enum(data) => {
+      let mut variants = Vec::new();
+      for variant in &data.variants {
+        let variant_attrs = SerdeAttrs::parse(&variant.attrs)?;
+        if variant_attrs.skip {
+          continue;
+        }
+        let name = variant_attrs.rename.clone().unwrap_or_else(|| {
+          let name = variant.ident.to_string();
+          match attrs.rename_all {
+            Some(rule) => rule.apply_to_variant(&name),
+            None => name,
+          }
+        });
+        variants.push(variant_type(
+          &name,
+          &attrs,
+          &variant_attrs,
+          &variant.fields,
+        )?);
+      }
+      if variants.is_empty() {
+        vec![literal("never")]
+      } else {
+        join(variants, " | ")
+      }
+    }
+    Data::Union(_) => {
+      return Err(Error::new_spanned(
+        &input.ident,
+        "TsType can't be derived for unions",
+      ))
+    }
+  };
+
+  for param in input.generics.type_params_mut() {
+    param.bounds.push(parse_quote!(::wry::webview::TsType));
+  }
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+  Ok(quote! {
+    impl #impl_generics ::wry::webview::TsType for #ident #ty_generics #where_clause {
+      fn ts_type() -> ::std::string::String {
+        [#(#parts),*].concat()
+      }
+    }
+  })
+}
+
+// The type of a struct, as the parts of its TypeScript type.
+fn struct_type(name: &str, attrs: &SerdeAttrs, fields: &Fields) -> Result<Vec<TokenStream2>> {
+  let name = attrs.rename.as_deref().unwrap_or(name);
+  if attrs.transparent {
+    for field in fields {
+      if !SerdeAttrs::parse(&field.attrs)?.skip {
+        return Ok(vec![field_type(&field.ty)]);
+      }
+    }
+  }
+  Ok(match fields {
+    Fields::Named(_) => {
+      let tag = attrs.tag.as_ref().map(|tag| (tag.as_str(), name));
+      object(tag, attrs, fields)?
+    }
+    Fields::Unnamed(_) => unnamed(fields)?,
+    Fields::Unit => vec![literal("null")],
+  })
+}
+
+// The type of an enum variant, depending on the representation of the enum.
+fn variant_type(
+  name: &str,
+  attrs: &SerdeAttrs,
+  variant_attrs: &SerdeAttrs,
+  fields: &Fields,
+) -> Result<Vec<TokenStream2>> {
+  // Internally tagged struct variants have the tag among their fields
+  if let (Some(tag), None, false, Fields::Named(_)) =
+    (&attrs.tag, &attrs.content, attrs.untagged, fields)
+  {
+    return object(Some((tag, name)), variant_attrs, fields);
+  }
+
+  let content = match fields {
+    Fields::Named(_) => Some(object(None, variant_attrs, fields)?),
+    Fields::Unnamed(_) => Some(unnamed(fields)?),
+    Fields::Unit => None,
+  };
+  let quoted = json_string(name);
+  Ok(
+    match (&attrs.tag, &attrs.content, attrs.untagged, content) {
+      (_, _, true, Some(content)) => content,
+      (_, _, true, None) => vec![literal("null")],
+      (Some(tag), Some(content_key), _, Some(content)) => wrap(
+        &format!("{{ {}: {}; {}: ", key(tag), quoted, key(content_key)),
+        content,
+        " }",
+      ),
+      (Some(tag), _, _, Some(content)) => {
+        wrap(&format!("{{ {}: {} }} & ", key(tag), quoted), content, "")
+      }
+      (Some(tag), _, _, None) => vec![literal(&format!("{{ {}: {} }}", key(tag), quoted))],
+      (None, _, _, Some(content)) => wrap(&format!("{{ {}: ", key(name)), content, " }"),
+      (None, _, _, None) => vec![literal(&quoted)],
+    },
+  )
+}
+
+// An object type like `{ tag: "Name"; a: A; b?: B } & Flattened`.
+fn object(
+  tag: Option<(&str, &str)>,
+  attrs: &SerdeAttrs,
+  fields: &Fields,
+) -> Result<Vec<TokenStream2>> {
+  let mut members = Vec::new();
+  if let Some((tag, name)) = tag {
+    members.push(vec![literal(&format!(
+      "{}: {}",
+      key(tag),
+      json_string(name)
+    ))]);
+  }
+  let mut flattened = Vec::new();
+  for field in fields {
+    let field_attrs = SerdeAttrs::parse(&field.attrs)?;
+    if field_attrs.skip {
+      continue;
+    }
+    if field_attrs.flatten {
+      flattened.push(field_type(&field.ty));
+      continue;
+    }
+    let name = field_attrs.rename.clone().unwrap_or_else(|| {
+      let name = field
+        .ident
+        .as_ref()
+        .map(|ident| ident.to_string())
+        .unwrap_or_default();
+      let name = name.trim_start_matches("r#").to_string();
+      match attrs.rename_all {
+        Some(rule) => rule.apply_to_field(&name),
+        None => name,
+      }
+    });
+    let optional = if field_attrs.optional || attrs.optional {
+      "?"
+    } else {
+      ""
+    };
+    members.push(vec![
+      literal(&format!("{}{}: ", key(&name), optional)),
+      field_type(&field.ty),
+    ]);
+  }
+
+  let mut parts = if members.is_empty() {
+    vec![literal("{}")]
+  } else {
+    wrap("{ ", join(members, "; "), " }")
+  };
+  for ty in flattened {
+    parts.push(literal(" & "));
+    parts.push(ty);
+  }
+  Ok(parts)
+}
+
+// The type of unnamed fields: the type of the field of a newtype, which `serde` serializes as
+// the field itself, or a tuple type like `[A, B]`.
+fn unnamed(fields: &Fields) -> Result<Vec<TokenStream2>> {
+  if fields.len() == 1 {
+    return Ok(fields.iter().map(|field| field_type(&field.ty)).collect());
+  }
+  let mut items = Vec::new();
+  for field in fields {
+    if !SerdeAttrs::parse(&field.attrs)?.skip {
+      items.push(vec![field_type(&field.ty)]);
+    }
+  }
+  Ok(wrap("[", join(items, ", "), "]"))
+}
+
+fn field_type(ty: &Type) -> TokenStream2 {
+  quote!(<#ty as ::wry::webview::TsType>::ts_type())
+}
+
+fn literal(s: &str) -> TokenStream2 {
+  quote!(::std::string::String::from(#s))
+}
+
+fn wrap(before: &str, parts: Vec<TokenStream2>, after: &str) -> Vec<TokenStream2> {
+  let mut wrapped = vec![literal(before)];
+  wrapped.extend(parts);
+  if !after.is_empty() {
+    wrapped.push(literal(after));
+  }
+  wrapped
+}
+
+fn join(items: Vec<Vec<TokenStream2>>, separator: &str) -> Vec<TokenStream2> {
+  let mut parts = Vec::new();
+  for (i, item) in items.into_iter().enumerate() {
+    if i > 0 {
+      parts.push(literal(separator));
+    }
+    parts.extend(item);
+  }
+  parts
+}
+
+// A string as a TypeScript string literal.
+fn json_string(s: &str) -> String {
+  let mut out = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+// An object key, quoted unless it's a valid identifier.
+fn key(name: &str) -> String {
+  let mut chars = name.chars();
+  let is_ident = match chars.next() {
+    Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+      chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+    }
+    _ => false,
+  };
+  if is_ident {
+    name.to_string()
+  } else {
+    json_string(name)
+  }
+}
+
+// The `serde` attributes of a container, a variant or a field.
+#[derive(Default)]
+struct SerdeAttrs {
+  rename: Option<String>,
+  rename_all: Option<RenameRule>,
+  tag: Option<String>,
+  content: Option<String>,
+  untagged: bool,
+  transparent: bool,
+  skip: bool,
+  flatten: bool,
+  optional: bool,
+}
+
+impl SerdeAttrs {
+  fn parse(attrs: &[Attribute]) -> Result<Self> {
+    let mut parsed = Self::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("serde")) {
+      let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        _ => continue,
+      };
+      for meta in list.nested {
+        let meta = match meta {
+          NestedMeta::Meta(meta) => meta,
+          NestedMeta::Lit(_) => continue,
+        };
+        let name = meta
+          .path()
+          .get_ident()
+          .map(|ident| ident.to_string())
+          .unwrap_or_default();
+        match (name.as_str(), &meta) {
+          ("rename", _) => parsed.rename = serialized_name(&meta),
+          ("rename_all", _) => {
+            if let Some(rule) = serialized_name(&meta) {
+              parsed.rename_all = Some(
+                RenameRule::from_str(&rule)
+                  .ok_or_else(|| Error::new_spanned(&meta, "unknown rename rule"))?,
+              );
+            }
+          }
+          ("tag", Meta::NameValue(value)) => parsed.tag = string(&value.lit),
+          ("content", Meta::NameValue(value)) => parsed.content = string(&value.lit),
+          ("untagged", _) => parsed.untagged = true,
+          ("transparent", _) => parsed.transparent = true,
+          ("skip", _) | ("skip_serializing", _) => parsed.skip = true,
+          ("flatten", _) => parsed.flatten = true,
+          ("default", _) | ("skip_serializing_if", _) => parsed.optional = true,
+          _ => (),
+        }
+      }
+    }
+    Ok(parsed)
+  }
+}
+
+// The name given by `rename = "..."` or `rename(serialize = "...")`.
+fn serialized_name(meta: &Meta) -> Option<String> {
+  match meta {
+    Meta::NameValue(value) => string(&value.lit),
+    Meta::List(list) => list.nested.iter().find_map(|nested| match nested {
+      NestedMeta::Meta(Meta::NameValue(value)) if value.path.is_ident("serialize") => {
+        string(&value.lit)
+      }
+      _ => None,
+    }),
+    Meta::Path(_) => None,
+  }
+}
+
+fn string(lit: &Lit) -> Option<String> {
+  match lit {
+    Lit::Str(s) => Some(s.value()),
+    _ => None,
+  }
+}
+
+// The case conversions of `rename_all`, as done by `serde`.
+#[derive(Clone, Copy)]
+enum RenameRule {
+  Lower,
+  Upper,
+  Pascal,
+  Camel,
+  Snake,
+  ScreamingSnake,
+  Kebab,
+  ScreamingKebab,
+}
+
+impl RenameRule {
+  fn from_str(rule: &str) -> Option<Self> {
+    Some(match rule {
+      "lowercase" => Self::Lower,
+      "UPPERCASE" => Self::Upper,
+      "PascalCase" => Self::Pascal,
+      "camelCase" => Self::Camel,
+      "snake_case" => Self::Snake,
+      "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+      "kebab-case" => Self::Kebab,
+      "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+      _ => return None,
+    })
+  }
+
+  // Variants are named in `PascalCase`.
+  fn apply_to_variant(self, variant: &str) -> String {
+    match self {
+      Self::Pascal => variant.to_string(),
+      Self::Lower => variant.to_ascii_lowercase(),
+      Self::Upper => variant.to_ascii_uppercase(),
+      Self::Camel => match variant.chars().next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + &variant[first.len_utf8()..],
+        None => variant.to_string(),
+      },
+      Self::Snake => {
+        let mut snake = String::new();
+        for (i, c) in variant.char_indices() {
+          if i > 0 && c.is_uppercase() {
+            snake.push('_');
+          }
+          snake.push(c.to_ascii_lowercase());
+        }
+        snake
+      }
+      Self::ScreamingSnake => Self::Snake.apply_to_variant(variant).to_ascii_uppercase(),
+      Self::Kebab => Self::Snake.apply_to_variant(variant).replace('_', "-"),
+      Self::ScreamingKebab => Self::ScreamingSnake
+        .apply_to_variant(variant)
+        .replace('_', "-"),
+    }
+  }
+
+  // Fields are named in `snake_case`.
+  fn apply_to_field(self, field: &str) -> String {
+    match self {
+      Self::Lower | Self::Snake => field.to_string(),
+      Self::Upper | Self::ScreamingSnake => field.to_ascii_uppercase(),
+      Self::Pascal => {
+        let mut pascal = String::new();
+        let mut capitalize = true;
+        for c in field.chars() {
+          if c == '_' {
+            capitalize = true;
+          } else if capitalize {
+            pascal.push(c.to_ascii_uppercase());
+            capitalize = false;
+          } else {
+            pascal.push(c);
+          }
+        }
+        pascal
+      }
+      Self::Camel => {
+        let pascal = Self::Pascal.apply_to_field(field);
+        match pascal.chars().next() {
+          Some(first) => first.to_ascii_lowercase().to_string() + &pascal[first.len_utf8()..],
+          None => pascal,
+        }
+      }
+      Self::Kebab => field.replace('_', "-"),
+      Self::ScreamingKebab => Self::ScreamingSnake.apply_to_field(field).replace('_', "-"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_rename_variants() {
+    let rules = [
+      ("lowercase", "unitvariant"),
+      ("UPPERCASE", "UNITVARIANT"),
+      ("PascalCase", "UnitVariant"),
+      ("camelCase", "unitVariant"),
+      ("snake_case", "unit_variant"),
+      ("SCREAMING_SNAKE_CASE", "UNIT_VARIANT"),
+      ("kebab-case", "unit-variant"),
+      ("SCREAMING-KEBAB-CASE", "UNIT-VARIANT"),
+    ];
+    for (rule, renamed) in &rules {
+      let rule = RenameRule::from_str(rule).unwrap();
+      assert_eq!(rule.apply_to_variant("UnitVariant"), *renamed);
+    }
+    assert!(RenameRule::from_str("Title Case").is_none());
+    // Identifiers may start with a non-ASCII character
+    assert_eq!(RenameRule::Camel.apply_to_variant("État"), "État");
+    assert_eq!(RenameRule::Camel.apply_to_variant(""), "");
+  }
+
+  #[test]
+  fn should_rename_fields() {
+    let rules = [
+      ("lowercase", "user_name"),
+      ("UPPERCASE", "USER_NAME"),
+      ("PascalCase", "UserName"),
+      ("camelCase", "userName"),
+      ("snake_case", "user_name"),
+      ("SCREAMING_SNAKE_CASE", "USER_NAME"),
+      ("kebab-case", "user-name"),
+      ("SCREAMING-KEBAB-CASE", "USER-NAME"),
+    ];
+    for (rule, renamed) in &rules {
+      let rule = RenameRule::from_str(rule).unwrap();
+      assert_eq!(rule.apply_to_field("user_name"), *renamed);
+    }
+    assert_eq!(RenameRule::Camel.apply_to_field("été_chaud"), "étéChaud");
+  }
+
+  #[test]
+  fn should_parse_serde_attributes() {
+    let input: DeriveInput = parse_quote! {
+      #[serde(rename(serialize = "user", deserialize = "u"), rename_all = "camelCase")]
+      #[serde(tag = "type", content = "data")]
+      struct User;
+    };
+    let attrs = SerdeAttrs::parse(&input.attrs).unwrap();
+    assert_eq!(attrs.rename.as_deref(), Some("user"));
+    assert!(matches!(attrs.rename_all, Some(RenameRule::Camel)));
+    assert_eq!(attrs.tag.as_deref(), Some("type"));
+    assert_eq!(attrs.content.as_deref(), Some("data"));
+    assert!(!attrs.untagged);
+  }
+
+  #[test]
+  fn should_reject_unsupported_types() {
+    let input: DeriveInput = parse_quote! {
+      union Bits {
+        a: u8,
+      }
+    };
+    assert!(ts_type(input).is_err());
+    let input: DeriveInput = parse_quote! {
+      #[serde(rename_all = "Title Case")]
+      struct User {
+        name: String,
+      }
+    };
+    assert!(ts_type(input).is_err());
+  }
+
+  #[test]
+  fn should_bound_type_parameters() {
+    let input: DeriveInput = parse_quote! {
+      struct Page<T> {
+        items: Vec<T>,
+      }
+    };
+    let tokens = ts_type(input).unwrap().to_string();
+    assert!(tokens.contains("impl < T : :: wry :: webview :: TsType >"));
+  }
+}
@@ -0,0 +1,329 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+// The web extension API isn't covered by the bindings of the UI process, and the libraries of
+// WebKitGTK are already loaded in the web process, so its functions are looked up at runtime the
+// same way wry does for the newer WebKitGTK methods.
+
+use std::{
+  ffi::{CStr, CString},
+  io::{self, BufRead, BufReader, Write},
+  mem,
+  os::{
+    raw::{c_char, c_int, c_ulong, c_void},
+    unix::net::UnixStream,
+  },
+  ptr,
+};
+
+use serde_json::{json, Value};
+
+type VariantGetString = unsafe extern "C" fn(*mut c_void, *mut usize) -> *const c_char;
+type SignalConnectData = unsafe extern "C" fn(
+  *mut c_void,
+  *const c_char,
+  *const c_void,
+  *mut c_void,
+  *const c_void,
+  c_int,
+) -> c_ulong;
+type GetUri = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type SetUri = unsafe extern "C" fn(*mut c_void, *const c_char);
+type GetHttpMethod = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type GetHttpHeaders = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type HeadersForeachFunc = unsafe extern "C" fn(*const c_char, *const c_char, *mut c_void);
+type HeadersForeach = unsafe extern "C" fn(*mut c_void, HeadersForeachFunc, *mut c_void);
+
+// `TRUE` and `FALSE` of GLib, returned by the `send-request` handler to cancel the request or not
+const CANCEL: c_int = 1;
+const SEND: c_int = 0;
+
+// The functions of GLib, WebKitGTK and libsoup used by the extension. The method and the headers
+// of the requests were only added in WebKitGTK 2.12.
+struct Symbols {
+  variant_get_string: Option<VariantGetString>,
+  signal_connect_data: Option<SignalConnectData>,
+  get_uri: Option<GetUri>,
+  set_uri: Option<SetUri>,
+  get_http_method: Option<GetHttpMethod>,
+  get_http_headers: Option<GetHttpHeaders>,
+  headers_foreach: Option<HeadersForeach>,
+}
+
+impl Symbols {
+  fn load() -> Self {
+    // Safety: the types match the signatures of the C functions
+    unsafe {
+      Symbols {
+        variant_get_string: symbol(b"g_variant_get_string\0"),
+        signal_connect_data: symbol(b"g_signal_connect_data\0"),
+        get_uri: symbol(b"webkit_uri_request_get_uri\0"),
+        set_uri: symbol(b"webkit_uri_request_set_uri\0"),
+        get_http_method: symbol(b"webkit_uri_request_get_http_method\0"),
+        get_http_headers: symbol(b"webkit_uri_request_get_http_headers\0"),
+        headers_foreach: symbol(b"soup_message_headers_foreach\0"),
+      }
+    }
+  }
+}
+
+// Look up a function of the libraries loaded in the process. `T` must be a function pointer type.
+unsafe fn symbol<T>(name: &[u8]) -> Option<T> {
+  let ptr = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const c_char);
+  if ptr.is_null() {
+    None
+  } else {
+    Some(mem::transmute_copy(&ptr))
+  }
+}
+
+// The state of the extension, given to the signal handlers and kept as long as the web process.
+// The signals are emitted on the main thread of the web process, one at a time.
+struct State {
+  symbols: Symbols,
+  // The connection to the webview, missing if it failed
+  connection: Option<Connection>,
+}
+
+struct Connection {
+  reader: BufReader<UnixStream>,
+  writer: UnixStream,
+}
+
+impl Connection {
+  fn connect(path: &str) -> io::Result<Self> {
+    let writer = UnixStream::connect(path)?;
+    Ok(Connection {
+      reader: BufReader::new(writer.try_clone()?),
+      writer,
+    })
+  }
+
+  // Send a request to the webview and wait for the decision of the interceptor.
+  fn ask(&mut self, request: &Value) -> io::Result<Value> {
+    let mut line = request.to_string();
+    line.push('\n');
+    self.writer.write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    if self.reader.read_line(&mut reply)? == 0 {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(serde_json::from_str(&reply)?)
+  }
+}
+
+/// What to do with a request, as told by the webview.
+#[derive(Debug, PartialEq)]
+enum Decision {
+  Allow,
+  Block,
+  Redirect(String),
+}
+
+impl Decision {
+  // Anything but a valid decision blocks the request, like a lost connection.
+  fn from_reply(reply: &Value) -> Self {
+    match (reply["action"].as_str(), reply["uri"].as_str()) {
+      (Some("allow"), _) => Decision::Allow,
+      (Some("redirect"), Some(uri)) => Decision::Redirect(uri.into()),
+      _ => Decision::Block,
+    }
+  }
+}
+
+// Whether the interceptor is asked about a request, like on Windows.
+fn is_intercepted(uri: &str) -> bool {
+  uri.starts_with("http://") || uri.starts_with("https://")
+}
+
+/// Called by WebKitGTK when it loads the extension in a web process, with the path of the socket
+/// of the webview as initialization data.
+///
+/// # Safety
+///
+/// Only WebKitGTK calls it, with a `WebKitWebExtension` and a string `GVariant`.
+#[no_mangle]
+pub unsafe extern "C" fn webkit_web_extension_initialize_with_user_data(
+  extension: *mut c_void,
+  user_data: *mut c_void,
+) {
+  let symbols = Symbols::load();
+  let (variant_get_string, signal_connect_data) =
+    match (symbols.variant_get_string, symbols.signal_connect_data) {
+      (Some(variant_get_string), Some(signal_connect_data)) => {
+        (variant_get_string, signal_connect_data)
+      }
+      _ => return,
+    };
+  if user_data.is_null() {
+    return;
+  }
+  let path = CStr::from_ptr(variant_get_string(user_data, ptr::null_mut()))
+    .to_string_lossy()
+    .into_owned();
+  // Without a connection the interceptor can't be asked, so every request is blocked
+  let connection = Connection::connect(&path).ok();
+  let state = Box::into_raw(Box::new(State {
+    symbols,
+    connection,
+  }));
+  signal_connect_data(
+    extension,
+    b"page-created\0".as_ptr() as *const c_char,
+    page_created as *const c_void,
+    state as *mut c_void,
+    ptr::null(),
+    0,
+  );
+}
+
+// `page-created` handler of the extension, hooking the requests of the page.
+unsafe extern "C" fn page_created(_extension: *mut c_void, page: *mut c_void, state: *mut c_void) {
+  if let Some(signal_connect_data) = (*(state as *mut State)).symbols.signal_connect_data {
+    signal_connect_data(
+      page,
+      b"send-request\0".as_ptr() as *const c_char,
+      send_request as *const c_void,
+      state,
+      ptr::null(),
+      0,
+    );
+  }
+}
+
+// `send-request` handler of a page, asking the interceptor what to do with the request.
+unsafe extern "C" fn send_request(
+  _page: *mut c_void,
+  request: *mut c_void,
+  _redirected_response: *mut c_void,
+  state: *mut c_void,
+) -> c_int {
+  let state = &mut *(state as *mut State);
+  let uri = match state.symbols.get_uri.map(|get_uri| get_uri(request)) {
+    Some(uri) if !uri.is_null() => CStr::from_ptr(uri).to_string_lossy().into_owned(),
+    _ => return SEND,
+  };
+  if !is_intercepted(&uri) {
+    return SEND;
+  }
+
+  let message = json!({
+    "uri": uri,
+    "method": method(&state.symbols, request),
+    "headers": headers(&state.symbols, request),
+  });
+  let decision = match state
+    .connection
+    .as_mut()
+    .map(|connection| connection.ask(&message))
+  {
+    Some(Ok(reply)) => Decision::from_reply(&reply),
+    Some(Err(_)) => {
+      state.connection = None;
+      Decision::Block
+    }
+    None => Decision::Block,
+  };
+  match decision {
+    Decision::Allow => SEND,
+    Decision::Block => CANCEL,
+    Decision::Redirect(uri) => match (state.symbols.set_uri, CString::new(uri)) {
+      (Some(set_uri), Ok(uri)) => {
+        set_uri(request, uri.as_ptr());
+        SEND
+      }
+      _ => CANCEL,
+    },
+  }
+}
+
+// Get the HTTP method of a request, `GET` without WebKitGTK 2.12.
+unsafe fn method(symbols: &Symbols, request: *mut c_void) -> String {
+  match symbols
+    .get_http_method
+    .map(|get_http_method| get_http_method(request))
+  {
+    Some(method) if !method.is_null() => CStr::from_ptr(method).to_string_lossy().into_owned(),
+    _ => "GET".into(),
+  }
+}
+
+// Get the HTTP headers of a request, as name and value pairs.
+unsafe fn headers(symbols: &Symbols, request: *mut c_void) -> Vec<(String, String)> {
+  let mut list: Vec<(String, String)> = Vec::new();
+  if let (Some(get_http_headers), Some(headers_foreach)) =
+    (symbols.get_http_headers, symbols.headers_foreach)
+  {
+    let headers = get_http_headers(request);
+    if !headers.is_null() {
+      unsafe extern "C" fn push(name: *const c_char, value: *const c_char, data: *mut c_void) {
+        let list = &mut *(data as *mut Vec<(String, String)>);
+        list.push((
+          CStr::from_ptr(name).to_string_lossy().into_owned(),
+          CStr::from_ptr(value).to_string_lossy().into_owned(),
+        ));
+      }
+      headers_foreach(headers, push, &mut list as *mut _ as *mut c_void);
+    }
+  }
+  list
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_read_decisions() {
+    assert_eq!(
+      Decision::from_reply(&json!({ "action": "allow" })),
+      Decision::Allow
+    );
+    assert_eq!(
+      Decision::from_reply(&json!({ "action": "block" })),
+      Decision::Block
+    );
+    assert_eq!(
+      Decision::from_reply(&json!({ "action": "redirect", "uri": "https://example.com/" })),
+      Decision::Redirect("https://example.com/".into())
+    );
+    // Malformed replies block the request
+    assert_eq!(
+      Decision::from_reply(&json!({ "action": "redirect" })),
+      Decision::Block
+    );
+    assert_eq!(Decision::from_reply(&json!(null)), Decision::Block);
+  }
+
+  #[test]
+  fn should_intercept_http_only() {
+    assert!(is_intercepted("http://example.com/"));
+    assert!(is_intercepted("https://example.com/app.js"));
+    assert!(!is_intercepted("wry-rpc://localhost/token"));
+    assert!(!is_intercepted("data:text/plain,wry"));
+  }
+
+  #[test]
+  fn should_ask_over_the_socket() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let mut connection = Connection {
+      reader: BufReader::new(client.try_clone().unwrap()),
+      writer: client,
+    };
+    let webview = std::thread::spawn(move || {
+      let mut reader = BufReader::new(server.try_clone().unwrap());
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      let request: Value = serde_json::from_str(&line).unwrap();
+      assert_eq!(request["uri"], "https://example.com/");
+      let mut server = server;
+      server.write_all(b"{\"action\":\"block\"}\n").unwrap();
+    });
+    let reply = connection
+      .ask(&json!({ "uri": "https://example.com/", "method": "GET", "headers": [] }))
+      .unwrap();
+    webview.join().unwrap();
+    assert_eq!(Decision::from_reply(&reply), Decision::Block);
+  }
+}
@@ -0,0 +1,17 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! WebKitGTK web extension running the request interceptor of [wry](https://docs.rs/wry) on
+//! Linux.
+//!
+//! WebKitGTK makes the requests of the pages in its web processes, where only a web extension can
+//! block or rewrite them. Build this crate and install `libwry_webextension.so` next to the
+//! executable: the webviews with a request interceptor load it in their web process, which asks
+//! the interceptor about every `http` and `https` request before sending it.
+//!
+//! The extension talks to its webview through the socket given in the initialization data, with a
+//! line of JSON per request and per decision. It has no API of its own.
+
+#[cfg(target_os = "linux")]
+mod extension;